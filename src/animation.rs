@@ -10,7 +10,8 @@ use crate::render;
 use crate::render::raytracing_config::RaytracingConfig;
 
 use crate::scene::materials;
-use materials::Material;
+use materials::{Material, MaterialId};
+use crate::scene::objects::{Shape, ObjectHandle};
 
 //extern crate nalgebra as na;
 //use na::Vector3;
@@ -59,14 +60,14 @@ pub fn render_camera_rotation (window:&Window, raytracing_config:RaytracingConfi
 	}
 }
 
-pub fn render_metal_fuzz_animation(window:&Window, raytracing_config:RaytracingConfig, scene:&mut Scene, starting_frame:u32, frames:u32, object_id:u32, fuzz_start:f64, fuzz_end:f64){
+pub fn render_metal_fuzz_animation(window:&Window, raytracing_config:RaytracingConfig, scene:&mut Scene, starting_frame:u32, frames:u32, material_id:MaterialId, fuzz_start:f64, fuzz_end:f64){
 	for i in 0..frames{
 		//Interpolate fuzz
 		let t = i as f64 / frames as f64;
 		let fuzz = lerp(fuzz_start, fuzz_end, t);
 
 		//Find material, necessary do in loop to prevent mutable reference from existing
-		let material = &mut scene.objects[object_id as usize].material;
+		let material = scene.material_mut(material_id);
 
 		match material{
 			Material::Metal(ref mut metal) =>{
@@ -97,11 +98,14 @@ pub fn render_light_tunnel_animation(window:&Window, raytracing_config:Raytracin
 		//Objects animation
 		let displacement = (20.0/60.0 * PI * oscilation_speed).cos() * oscilation_size;//change to time to animate positions
 
-		for obj_id in starting_obj_id..scene.objects.len(){
-			if obj_id % 2 == 0{
-				scene.spheres[obj_id].position.z = displacement + distance;
-			} else {
-				scene.spheres[obj_id].position.z = - displacement - distance;
+		let handles:Vec<ObjectHandle> = scene.handles().skip(starting_obj_id).collect();
+		for handle in handles{
+			if let Shape::Sphere(ref mut sphere) = scene.shape_mut(handle){
+				if handle.index() % 2 == 0{
+					sphere.position.z = displacement + distance;
+				} else {
+					sphere.position.z = - displacement - distance;
+				}
 			}
 		}
 
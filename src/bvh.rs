@@ -0,0 +1,453 @@
+#![allow(dead_code)]
+
+use crate::vector3::Vector3;
+use crate::intersection::Ray;
+
+const BIN_COUNT:usize = 12;
+const MAX_LEAF_PRIMITIVES:usize = 4;
+const TRAVERSAL_COST:f64 = 1.0;
+const INTERSECTION_COST:f64 = 1.0;
+const STACK_SIZE:usize = 64;
+//Past this depth nodes are split at the median so traversal never overflows the stack
+const MAX_SAH_DEPTH:usize = 40;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Aabb{
+	pub min:Vector3,
+	pub max:Vector3,
+}
+
+impl Aabb{
+	pub const fn new(min:Vector3, max:Vector3) -> Aabb{
+		Aabb{
+			min,
+			max,
+		}
+	}
+
+	pub const fn empty() -> Aabb{
+		Aabb{
+			min:Vector3::new(f64::MAX, f64::MAX, f64::MAX),
+			max:Vector3::new(f64::MIN, f64::MIN, f64::MIN),
+		}
+	}
+
+	pub fn union(&self, other:&Aabb) -> Aabb{
+		Aabb{
+			min:Vector3::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+			max:Vector3::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z)),
+		}
+	}
+
+	pub fn grow(&mut self, point:&Vector3){
+		self.min = Vector3::new(self.min.x.min(point.x), self.min.y.min(point.y), self.min.z.min(point.z));
+		self.max = Vector3::new(self.max.x.max(point.x), self.max.y.max(point.y), self.max.z.max(point.z));
+	}
+
+	pub fn centroid(&self) -> Vector3{
+		return 0.5 * (self.min + self.max);
+	}
+
+	pub fn surface_area(&self) -> f64{
+		let d = self.max - self.min;
+		if d.x < 0.0 || d.y < 0.0 || d.z < 0.0 {return 0.0;}
+		return 2.0 * (d.x * d.y + d.y * d.z + d.z * d.x);
+	}
+
+	pub fn is_finite(&self) -> bool{
+		return self.min.x.is_finite() && self.min.y.is_finite() && self.min.z.is_finite() &&
+			self.max.x.is_finite() && self.max.y.is_finite() && self.max.z.is_finite();
+	}
+
+	pub fn largest_axis(&self) -> usize{
+		let d = self.max - self.min;
+		if d.x > d.y && d.x > d.z {return 0;}
+		if d.y > d.z {return 1;}
+		return 2;
+	}
+
+	//Slab test, returns the entry distance or f64::MAX if the box is missed
+	pub fn intersection_distance(&self, origin:&Vector3, inv_direction:&Vector3, max_distance:f64) -> f64{
+		let tx1 = (self.min.x - origin.x) * inv_direction.x;
+		let tx2 = (self.max.x - origin.x) * inv_direction.x;
+		let mut t_min = tx1.min(tx2);
+		let mut t_max = tx1.max(tx2);
+
+		let ty1 = (self.min.y - origin.y) * inv_direction.y;
+		let ty2 = (self.max.y - origin.y) * inv_direction.y;
+		t_min = t_min.max(ty1.min(ty2));
+		t_max = t_max.min(ty1.max(ty2));
+
+		let tz1 = (self.min.z - origin.z) * inv_direction.z;
+		let tz2 = (self.max.z - origin.z) * inv_direction.z;
+		t_min = t_min.max(tz1.min(tz2));
+		t_max = t_max.min(tz1.max(tz2));
+
+		if t_max >= t_min.max(0.0) && t_min < max_distance{
+			return t_min;
+		}
+		return f64::MAX;
+	}
+}
+
+//Flattened node, children of interior nodes are stored as [node + 1, offset]
+#[derive(Copy, Clone)]
+struct BvhNode{
+	bounds:Aabb,
+	offset:u32,
+	count:u32,
+	axis:u8,
+}
+
+pub struct Bvh{
+	nodes:Vec<BvhNode>,
+	indices:Vec<u32>,
+	//Primitives with infinite bounds (planes) can't be placed in the tree and are always tested
+	unbounded:Vec<u32>,
+}
+
+struct BuildPrimitive{
+	bounds:Aabb,
+	centroid:Vector3,
+	index:u32,
+}
+
+#[derive(Copy, Clone)]
+struct Bin{
+	bounds:Aabb,
+	count:usize,
+}
+
+impl Bvh{
+	pub fn empty() -> Bvh{
+		Bvh{
+			nodes:Vec::new(),
+			indices:Vec::new(),
+			unbounded:Vec::new(),
+		}
+	}
+
+	//Builds a SAH bvh over the primitive bounds, primitives are referenced by their index in the slice
+	pub fn build(bounds:&[Aabb]) -> Bvh{
+		let mut primitives:Vec<BuildPrimitive> = bounds.iter().enumerate().filter(|(_, b)| b.is_finite()).map(|(i, b)| BuildPrimitive{
+			bounds:*b,
+			centroid:b.centroid(),
+			index:i as u32,
+		}).collect();
+
+		let mut bvh = Bvh{
+			nodes:Vec::with_capacity(2 * primitives.len()),
+			indices:Vec::with_capacity(primitives.len()),
+			unbounded:(0..bounds.len()).filter(|i| !bounds[*i].is_finite()).map(|i| i as u32).collect(),
+		};
+
+		if !primitives.is_empty(){
+			bvh.build_recursive(&mut primitives[..], 0);
+		}
+
+		return bvh;
+	}
+
+	fn build_recursive(&mut self, primitives:&mut [BuildPrimitive], depth:usize) -> usize{
+		let node_index = self.nodes.len();
+
+		let mut bounds = Aabb::empty();
+		let mut centroid_bounds = Aabb::empty();
+		for p in primitives.iter(){
+			bounds = bounds.union(&p.bounds);
+			centroid_bounds.grow(&p.centroid);
+		}
+
+		self.nodes.push(BvhNode{
+			bounds,
+			offset:0,
+			count:0,
+			axis:0,
+		});
+
+		let split = if primitives.len() <= MAX_LEAF_PRIMITIVES{
+			None
+		}else if depth >= MAX_SAH_DEPTH{
+			Some(Bvh::median_split(primitives, &centroid_bounds))
+		}else{
+			Bvh::find_split(primitives, &bounds, &centroid_bounds)
+		};
+
+		match split{
+			None => {
+				//Leaf
+				self.nodes[node_index].offset = self.indices.len() as u32;
+				self.nodes[node_index].count = primitives.len() as u32;
+				for p in primitives.iter(){
+					self.indices.push(p.index);
+				}
+			},
+			Some((split_axis, mid)) => {
+				self.build_recursive(&mut primitives[..mid], depth + 1);
+				let second_child = self.build_recursive(&mut primitives[mid..], depth + 1);
+				self.nodes[node_index].offset = second_child as u32;
+				self.nodes[node_index].axis = split_axis as u8;
+			},
+		}
+
+		return node_index;
+	}
+
+	fn median_split(primitives:&mut [BuildPrimitive], centroid_bounds:&Aabb) -> (usize, usize){
+		let split_axis = centroid_bounds.largest_axis();
+		let mid = primitives.len() / 2;
+		primitives.select_nth_unstable_by(mid, |a, b| a.centroid.component(split_axis).total_cmp(&b.centroid.component(split_axis)));
+		return (split_axis, mid);
+	}
+
+	//Binned surface area heuristic, returns the split axis and the partition point
+	fn find_split(primitives:&mut [BuildPrimitive], bounds:&Aabb, centroid_bounds:&Aabb) -> Option<(usize, usize)>{
+		let split_axis = centroid_bounds.largest_axis();
+		let axis_min = centroid_bounds.min.component(split_axis);
+		let axis_max = centroid_bounds.max.component(split_axis);
+		let extent = axis_max - axis_min;
+
+		if !(extent > 0.0) || !extent.is_finite(){
+			//All centroids overlap, splitting in the middle still keeps leaves small
+			if primitives.len() <= 2 * MAX_LEAF_PRIMITIVES {return None;}
+			return Some(Bvh::median_split(primitives, centroid_bounds));
+		}
+
+		let bin_of = |p:&BuildPrimitive| -> usize{
+			let b = (BIN_COUNT as f64 * (p.centroid.component(split_axis) - axis_min) / extent) as usize;
+			return b.min(BIN_COUNT - 1);
+		};
+
+		let mut bins = [Bin{bounds:Aabb::empty(), count:0}; BIN_COUNT];
+		for p in primitives.iter(){
+			let b = bin_of(p);
+			bins[b].count += 1;
+			bins[b].bounds = bins[b].bounds.union(&p.bounds);
+		}
+
+		//Sweep from the right to get the cost of every split plane
+		let mut right_area = [0.0; BIN_COUNT];
+		let mut right_count = [0; BIN_COUNT];
+		let mut accumulated = Aabb::empty();
+		let mut count = 0;
+		for i in (1..BIN_COUNT).rev(){
+			accumulated = accumulated.union(&bins[i].bounds);
+			count += bins[i].count;
+			right_area[i] = accumulated.surface_area();
+			right_count[i] = count;
+		}
+
+		let mut best_cost = f64::MAX;
+		let mut best_bin = 0;
+		accumulated = Aabb::empty();
+		count = 0;
+		for i in 0..BIN_COUNT - 1{
+			accumulated = accumulated.union(&bins[i].bounds);
+			count += bins[i].count;
+			let cost = accumulated.surface_area() * count as f64 + right_area[i + 1] * right_count[i + 1] as f64;
+			if cost < best_cost{
+				best_cost = cost;
+				best_bin = i;
+			}
+		}
+
+		let area = bounds.surface_area();
+		let split_cost = TRAVERSAL_COST + INTERSECTION_COST * best_cost / area;
+		let leaf_cost = INTERSECTION_COST * primitives.len() as f64;
+
+		if split_cost >= leaf_cost && primitives.len() <= 2 * MAX_LEAF_PRIMITIVES{
+			return None;
+		}
+
+		//Partition primitives
+		let mut mid = 0;
+		for i in 0..primitives.len(){
+			if bin_of(&primitives[i]) <= best_bin{
+				primitives.swap(i, mid);
+				mid += 1;
+			}
+		}
+
+		if mid == 0 || mid == primitives.len(){
+			mid = primitives.len() / 2;
+		}
+
+		return Some((split_axis, mid));
+	}
+
+	//Finds the closest primitive, intersect_primitive receives the primitive index and
+	//the current closest distance and returns the hit distance or f64::MAX
+	pub fn intersect<F>(&self, ray:&Ray, max_distance:f64, mut intersect_primitive:F) -> Option<(usize, f64)>
+		where F: FnMut(usize, f64) -> f64{
+
+		let mut closest_distance = max_distance;
+		let mut closest_id:Option<usize> = None;
+
+		for primitive in &self.unbounded{
+			let primitive = *primitive as usize;
+			let distance = intersect_primitive(primitive, closest_distance);
+			if distance < closest_distance{
+				closest_distance = distance;
+				closest_id = Some(primitive);
+			}
+		}
+
+		if self.nodes.is_empty() {return closest_id.map(|id| (id, closest_distance));}
+
+		let inv_direction = Vector3::new(1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z);
+		let direction_negative = [inv_direction.x < 0.0, inv_direction.y < 0.0, inv_direction.z < 0.0];
+
+		let mut stack = [0u32; STACK_SIZE];
+		let mut stack_size = 0;
+		let mut node_index = 0;
+
+		loop{
+			let node = &self.nodes[node_index];
+
+			if node.bounds.intersection_distance(&ray.origin, &inv_direction, closest_distance) != f64::MAX{
+				if node.count > 0{
+					//Leaf
+					for i in node.offset..node.offset + node.count{
+						let primitive = self.indices[i as usize] as usize;
+						let distance = intersect_primitive(primitive, closest_distance);
+						if distance < closest_distance{
+							closest_distance = distance;
+							closest_id = Some(primitive);
+						}
+					}
+				}else{
+					//Visit near child first
+					if direction_negative[node.axis as usize]{
+						stack[stack_size] = node_index as u32 + 1;
+						node_index = node.offset as usize;
+					}else{
+						stack[stack_size] = node.offset;
+						node_index += 1;
+					}
+					stack_size += 1;
+					continue;
+				}
+			}
+
+			if stack_size == 0 {break;}
+			stack_size -= 1;
+			node_index = stack[stack_size] as usize;
+		}
+
+		return closest_id.map(|id| (id, closest_distance));
+	}
+
+	//Returns true as soon as any primitive is hit before max_distance, used for shadow rays
+	pub fn intersect_any<F>(&self, ray:&Ray, max_distance:f64, mut intersect_primitive:F) -> bool
+		where F: FnMut(usize, f64) -> f64{
+
+		for primitive in &self.unbounded{
+			if intersect_primitive(*primitive as usize, max_distance) < max_distance{
+				return true;
+			}
+		}
+
+		if self.nodes.is_empty() {return false;}
+
+		let inv_direction = Vector3::new(1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z);
+
+		let mut stack = [0u32; STACK_SIZE];
+		let mut stack_size = 0;
+		let mut node_index = 0;
+
+		loop{
+			let node = &self.nodes[node_index];
+
+			if node.bounds.intersection_distance(&ray.origin, &inv_direction, max_distance) != f64::MAX{
+				if node.count > 0{
+					for i in node.offset..node.offset + node.count{
+						let primitive = self.indices[i as usize] as usize;
+						if intersect_primitive(primitive, max_distance) < max_distance{
+							return true;
+						}
+					}
+				}else{
+					stack[stack_size] = node.offset;
+					stack_size += 1;
+					node_index += 1;
+					continue;
+				}
+			}
+
+			if stack_size == 0 {break;}
+			stack_size -= 1;
+			node_index = stack[stack_size] as usize;
+		}
+
+		return false;
+	}
+
+	pub fn bounds(&self) -> Aabb{
+		if self.nodes.is_empty() {return Aabb::empty();}
+		return self.nodes[0].bounds;
+	}
+}
+
+#[cfg(test)]
+mod tests{
+	use super::*;
+	use crate::scene::objects::Sphere;
+	use rand::{Rng, SeedableRng};
+	use rand::rngs::StdRng;
+
+	fn random_spheres(rng:&mut StdRng, count:usize) -> Vec<Sphere>{
+		return (0..count).map(|_| {
+			let position = Vector3::new(rng.gen_range(-50.0..50.0), rng.gen_range(-50.0..50.0), rng.gen_range(-50.0..50.0));
+			Sphere::create_sphere(position, rng.gen_range(0.1..3.0))
+		}).collect();
+	}
+
+	fn random_ray(rng:&mut StdRng) -> Ray{
+		let origin = Vector3::new(rng.gen_range(-60.0..60.0), rng.gen_range(-60.0..60.0), rng.gen_range(-60.0..60.0));
+		let direction = Vector3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)).normalize();
+		return Ray{origin, direction, wavelengths:None};
+	}
+
+	fn brute_force_closest(spheres:&[Sphere], ray:&Ray) -> Option<(usize, f64)>{
+		let mut closest:Option<(usize, f64)> = None;
+		for (i, sphere) in spheres.iter().enumerate(){
+			let distance = sphere.intersection_distance(ray);
+			if distance < closest.map_or(f64::MAX, |(_, d)| d){
+				closest = Some((i, distance));
+			}
+		}
+		return closest;
+	}
+
+	fn check_against_brute_force(spheres:&[Sphere], rng:&mut StdRng){
+		let bounds:Vec<Aabb> = spheres.iter().map(|s| s.bounds()).collect();
+		let bvh = Bvh::build(&bounds);
+
+		for _ in 0..2000{
+			let ray = random_ray(rng);
+			let expected = brute_force_closest(spheres, &ray);
+			let found = bvh.intersect(&ray, f64::MAX, |i, _| spheres[i].intersection_distance(&ray));
+			assert_eq!(found.map(|(i, _)| i), expected.map(|(i, _)| i));
+			assert_eq!(found.map(|(_, d)| d), expected.map(|(_, d)| d));
+
+			let max_distance = rng.gen_range(0.0..150.0);
+			let occluded = bvh.intersect_any(&ray, max_distance, |i, _| spheres[i].intersection_distance(&ray));
+			assert_eq!(occluded, expected.is_some_and(|(_, d)| d < max_distance));
+		}
+	}
+
+	#[test]
+	fn traversal_matches_brute_force(){
+		let mut rng = StdRng::seed_from_u64(1);
+		let spheres = random_spheres(&mut rng, 1000);
+		check_against_brute_force(&spheres, &mut rng);
+	}
+
+	#[test]
+	fn traversal_matches_brute_force_with_shared_centroids(){
+		//Identical centroids can't be binned, the build falls back to median splits
+		let mut rng = StdRng::seed_from_u64(2);
+		let spheres:Vec<Sphere> = (0..200).map(|i| Sphere::create_sphere(Vector3::new(0.0, 0.0, 0.0), 1.0 + 0.1 * i as f64)).collect();
+		check_against_brute_force(&spheres, &mut rng);
+	}
+}
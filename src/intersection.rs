@@ -3,14 +3,29 @@ use scene::Scene;
 use scene::objects::Object;
 use scene::objects::Shape;
 use scene::objects::Sphere;
+use scene::objects::Mesh;
+use scene::objects::Plane;
+use scene::objects::Quad;
+use scene::objects::Cuboid;
+use scene::objects::Cylinder;
+use scene::objects::Cone;
+use scene::objects::Disk;
+use scene::objects::Capsule;
+use scene::objects::Instance;
 
 //extern crate nalgebra as na;
 //use na::Vector3;
 use crate::vector3::Vector3;
+use crate::bvh::Aabb;
+use crate::spectrum::Wavelengths;
+use crate::sampling;
+use crate::sampling::orthonormal_basis;
 
 pub struct Ray{
 	pub origin:Vector3,
 	pub direction:Vector3,
+	//Set in spectral mode, the colors carried by the ray are then values at these wavelengths
+	pub wavelengths:Option<Wavelengths>,
 }
 
 pub enum Hit<'a>{
@@ -20,70 +35,1096 @@ pub enum Hit<'a>{
 
 pub struct HitData<'a>{
 	pub point:Vector3,
+	//Shading normal, outward like geometric_norm but moved by smooth mesh normals and normal maps
 	pub norm:Vector3,
+	//Normal of the actual surface, used for ray offsets and shadowing
+	pub geometric_norm:Vector3,
 	pub inside:bool,
 	pub distance:f64,
 	pub object:&'a Object,
+	pub uv:(f64, f64),
+	//Unit tangent along increasing u, orthogonal to norm, and norm x tangent
+	pub tangent:Vector3,
+	pub bitangent:Vector3,
 }
 
-fn test(scene:&Scene) -> bool{
-	return scene.objects.len() == scene.spheres.len();
+//Closest hit on a shape, primitive and barycentric u, v are only used by meshes
+#[derive(Copy, Clone)]
+pub struct ShapeHit{
+	pub distance:f64,
+	pub primitive:u32,
+	pub u:f64,
+	pub v:f64,
 }
 
-pub fn raycast<'a>(scene:&'a Scene, ray:&'a Ray) -> Hit<'a>{
-	//let mut closest_hit = Hit::Nothing;
-	let mut closest_distance = std::f64::MAX;
-	let mut closest_id: i32 = -1;
-
-	//assert!(test(scene), "Different number of spheres and objects");
-	/*
-	for obj in &scene.objects{
-		let hit = obj.intersection(ray);
-		
-		match hit{
-			Hit::Nothing => {continue;},
-			Hit::Something(ref hit_data) => {
-				
-				if hit_data.distance < closest_distance{
-					closest_distance = hit_data.distance;
-					closest_hit = hit;
-				}
-			},
-		}
+impl ShapeHit{
+	pub const NOTHING:ShapeHit = ShapeHit{distance:f64::MAX, primitive:0, u:0.0, v:0.0};
+
+	fn at(distance:f64) -> ShapeHit{
+		return ShapeHit{distance, primitive:0, u:0.0, v:0.0};
 	}
+}
 
-	return closest_hit;
-	*/
-	
-	for i in 0..scene.spheres.len() {
-		let distance = scene.spheres[i].intersection_distance(ray);
+//Closest hit, the bvh of the scene must be up to date with its geometry
+pub fn raycast<'a>(scene:&'a Scene, ray:&'a Ray) -> Hit<'a>{
+	let objects = scene.objects();
+	let mut closest_hit = ShapeHit::NOTHING;
 
-		if distance < closest_distance {
-			closest_distance = distance;
-			closest_id = i as i32;
+	let closest = scene.bvh().intersect(ray, f64::MAX, |i, max_distance| {
+		let shape_hit = objects[i].shape.intersection_distance(ray, max_distance);
+		if shape_hit.distance < max_distance{
+			closest_hit = shape_hit;
 		}
-	}
+		return shape_hit.distance;
+	});
 
-	if closest_id >= 0 {
-		let id = closest_id as usize;
-		return scene.spheres[id].get_hit_data(&scene.objects[id], closest_distance, ray);
+	if let Some((id, _)) = closest {
+		return objects[id].shape.get_hit_data(&objects[id], &closest_hit, ray);
 	}
 	
 	return Hit::Nothing;
 }
 
+//Shadow ray test, stops at the first primitive found before max_distance. Same bvh requirement as raycast.
+pub fn occluded(scene:&Scene, ray:&Ray, max_distance:f64) -> bool{
+	let objects = scene.objects();
+	return scene.bvh().intersect_any(ray, max_distance, |i, max_distance| objects[i].shape.intersection_distance(ray, max_distance).distance);
+}
+
 impl Object{
 	pub fn intersection(&self, ray:&Ray) -> Hit{
 		match self.shape{
 			Shape::Sphere(ref s) => {
 				return s.intersection(self, ray);
 			},
+			_ => {
+				let shape_hit = self.shape.intersection_distance(ray, f64::MAX);
+				if shape_hit.distance == f64::MAX {return Hit::Nothing;}
+				return self.shape.get_hit_data(self, &shape_hit, ray);
+			},
+		}
+	}
+}
+
+impl Shape{
+	pub fn bounds(&self) -> Aabb{
+		match self{
+			Shape::Sphere(s) => s.bounds(),
+			Shape::Plane(_) => Aabb::new(Vector3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY), Vector3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY)),
+			Shape::Quad(q) => q.bounds(),
+			Shape::Cuboid(b) => Aabb::new(b.min, b.max),
+			Shape::Cylinder(c) => c.bounds(),
+			Shape::Cone(c) => c.bounds(),
+			Shape::Disk(d) => disk_bounds(&d.position, &d.normal, d.radius),
+			Shape::Capsule(c) => c.bounds(),
+			Shape::Mesh(m) => m.bvh.bounds(),
+			Shape::Instance(i) => i.bounds(),
+		}
+	}
+
+	pub fn intersection_distance(&self, ray:&Ray, max_distance:f64) -> ShapeHit{
+		match self{
+			Shape::Sphere(s) => ShapeHit::at(s.intersection_distance(ray)),
+			Shape::Plane(p) => ShapeHit::at(p.intersection_distance(ray)),
+			Shape::Quad(q) => ShapeHit::at(q.intersection_distance(ray)),
+			Shape::Cuboid(b) => ShapeHit::at(b.intersection_distance(ray)),
+			Shape::Cylinder(c) => ShapeHit::at(c.intersection_distance(ray)),
+			Shape::Cone(c) => ShapeHit::at(c.intersection_distance(ray)),
+			Shape::Disk(d) => ShapeHit::at(d.intersection_distance(ray)),
+			Shape::Capsule(c) => ShapeHit::at(c.intersection_distance(ray)),
+			Shape::Mesh(m) => m.intersection_distance(ray, max_distance),
+			Shape::Instance(i) => i.intersection_distance(ray, max_distance),
+		}
+	}
+
+	pub fn get_hit_data<'a>(&self, object:&'a Object, shape_hit:&ShapeHit, ray:&Ray) -> Hit<'a>{
+		match self{
+			Shape::Sphere(s) => s.get_hit_data(object, shape_hit.distance, ray),
+			Shape::Plane(p) => p.get_hit_data(object, shape_hit.distance, ray),
+			Shape::Quad(q) => q.get_hit_data(object, shape_hit.distance, ray),
+			Shape::Mesh(m) => m.get_hit_data(object, shape_hit, ray),
+			Shape::Instance(i) => i.get_hit_data(object, shape_hit, ray),
+			_ => {
+				//Closed solids and disks only need the outward normal at the hit point
+				let point = ray.origin + shape_hit.distance * ray.direction;
+				let norm = self.normal_at(&point);
+				let (tangent, bitangent) = tangent_frame(&norm, &self.tangent_at(&point));
+
+				return Hit::Something(HitData{
+					point:point,
+					norm:norm,
+					geometric_norm:norm,
+					inside:ray.direction.dot(&norm) > 0.0,
+					distance:shape_hit.distance,
+					object:object,
+					uv:self.uv_at(&point),
+					tangent,
+					bitangent,
+				});
+			},
+		}
+	}
+
+	//Outward normal of a point on the surface of an analytic shape, meshes need the hit triangle
+	pub fn normal_at(&self, point:&Vector3) -> Vector3{
+		match self{
+			Shape::Sphere(s) => (point - s.position).normalize(),
+			Shape::Plane(p) => p.normal,
+			Shape::Quad(q) => q.normal(),
+			Shape::Cuboid(b) => b.normal_at(point),
+			Shape::Cylinder(c) => c.normal_at(point),
+			Shape::Cone(c) => c.normal_at(point),
+			Shape::Disk(d) => d.normal,
+			Shape::Capsule(c) => c.normal_at(point),
+			Shape::Mesh(_) => unreachable!("Mesh normals depend on the hit triangle"),
+			Shape::Instance(i) => i.transform.normal_to_world(&i.shape.normal_at(&i.transform.point_to_object(point))),
+		}
+	}
+
+	//Surface coordinates of a point on an analytic shape, meshes interpolate their vertex uvs instead
+	pub fn uv_at(&self, point:&Vector3) -> (f64, f64){
+		match self{
+			Shape::Sphere(s) => s.uv_at(point),
+			Shape::Plane(p) => p.uv_at(point),
+			Shape::Quad(q) => q.planar_coordinates(point),
+			Shape::Cuboid(b) => b.uv_at(point),
+			Shape::Cylinder(c) => c.uv_at(point),
+			Shape::Cone(c) => c.uv_at(point),
+			Shape::Disk(d) => d.uv_at(point),
+			Shape::Capsule(c) => c.uv_at(point),
+			Shape::Mesh(_) => unreachable!("Mesh uvs depend on the hit triangle"),
+			Shape::Instance(i) => i.shape.uv_at(&i.transform.point_to_object(point)),
+		}
+	}
+
+	//Direction of increasing u on an analytic shape, not normalized and may be zero at poles
+	pub fn tangent_at(&self, point:&Vector3) -> Vector3{
+		match self{
+			Shape::Sphere(s) => s.tangent_at(point),
+			Shape::Plane(p) => orthonormal_basis(&p.normal).0,
+			Shape::Quad(q) => q.u,
+			Shape::Cuboid(b) => b.tangent_at(point),
+			Shape::Cylinder(c) => c.axis.cross(&(point - c.base)),
+			Shape::Cone(c) => c.axis.cross(&(point - c.base)),
+			Shape::Disk(d) => orthonormal_basis(&d.normal).0,
+			Shape::Capsule(c) => (c.b - c.a).cross(&(point - c.a)),
+			Shape::Mesh(_) => unreachable!("Mesh tangents depend on the hit triangle"),
+			Shape::Instance(i) => i.transform.vector_to_world(&i.shape.tangent_at(&i.transform.point_to_object(point))),
+		}
+	}
+
+	//Whether sample_area can pick points on the shape, planes are unbounded and empty shapes have no area
+	pub fn has_area(&self) -> bool{
+		match self{
+			Shape::Plane(_) => false,
+			Shape::Instance(i) => i.shape.has_area(),
+			_ => self.area() > 0.0,
+		}
+	}
+
+	//Surface area of a shape with a uniform area density, instances stretch theirs and only know it per point
+	fn area(&self) -> f64{
+		match self{
+			Shape::Sphere(s) => 4.0 * std::f64::consts::PI * s.radius * s.radius,
+			Shape::Plane(_) => f64::INFINITY,
+			Shape::Quad(q) => q.u.cross(&q.v).norm(),
+			Shape::Cuboid(b) => b.area(),
+			Shape::Cylinder(c) => c.area(),
+			Shape::Cone(c) => c.area(),
+			Shape::Disk(d) => std::f64::consts::PI * d.radius * d.radius,
+			Shape::Capsule(c) => c.area(),
+			Shape::Mesh(m) => m.area(),
+			Shape::Instance(_) => unreachable!("Instances have no uniform area density"),
+		}
+	}
+
+	//Uniform point on the surface with its outward normal and the density per unit area, None if has_area is false
+	pub fn sample_area(&self, u:(f64, f64)) -> Option<(Vector3, Vector3, f64)>{
+		if !self.has_area() {return None;}
+
+		let point = match self{
+			Shape::Mesh(m) => return Some(m.sample_area(u)),
+			Shape::Instance(i) => return i.sample_area(u),
+			Shape::Sphere(s) => {
+				let (direction, _) = sampling::uniform_sphere(u.0, u.1);
+				s.position + s.radius * direction
+			},
+			Shape::Quad(q) => q.position + u.0 * q.u + u.1 * q.v,
+			Shape::Cuboid(b) => b.sample_point(u),
+			Shape::Cylinder(c) => c.sample_point(u),
+			Shape::Cone(c) => c.sample_point(u),
+			Shape::Disk(d) => {
+				let ((x, y), _) = sampling::uniform_disk(u.0, u.1);
+				d.position + d.radius * sampling::to_basis(&d.normal, &Vector3::new(x, y, 0.0))
+			},
+			Shape::Capsule(c) => c.sample_point(u),
+			Shape::Plane(_) => unreachable!(),
+		};
+		return Some((point, self.normal_at(&point), 1.0 / self.area()));
+	}
+
+	//Density of sample_area at a point of the surface with its outward normal
+	pub fn area_pdf(&self, point:&Vector3, normal:&Vector3) -> f64{
+		match self{
+			Shape::Plane(_) => 0.0,
+			Shape::Instance(i) => i.area_pdf(point, normal),
+			_ => 1.0 / self.area(),
+		}
+	}
+
+	//Point of the surface for a light sample from reference. Spheres seen from outside only sample the
+	//cone of directions they cover, the other shapes sample their whole area.
+	pub fn sample_from(&self, reference:&Vector3, u:(f64, f64)) -> Option<Vector3>{
+		if let Shape::Sphere(s) = self{
+			if let Some(point) = s.sample_cone(reference, u){
+				return Some(point);
+			}
+		}
+		return self.sample_area(u).map(|(point, _, _)| point);
+	}
+
+	//Solid angle density of sample_from at a point of the surface with its outward normal
+	pub fn pdf_from(&self, reference:&Vector3, point:&Vector3, normal:&Vector3) -> f64{
+		if let Shape::Sphere(s) = self{
+			if let Some(cos_max) = s.cone_cos_max(reference){
+				return sampling::uniform_cone_pdf(cos_max);
+			}
+		}
+
+		let to_point = point - *reference;
+		let square_distance = to_point.norm_squared();
+		let cos = normal.dot(&to_point).abs() / square_distance.sqrt();
+		if cos <= 0.0 {return 0.0;}
+		return self.area_pdf(point, normal) * square_distance / cos;
+	}
+}
+
+//Tangent made orthogonal to norm and the bitangent completing the frame, any frame if the tangent is parallel to norm
+pub fn tangent_frame(norm:&Vector3, tangent:&Vector3) -> (Vector3, Vector3){
+	let projected = tangent - tangent.dot(norm) * norm;
+	if projected.norm_squared() < 1e-12 {return orthonormal_basis(norm);}
+
+	let tangent = projected.normalize();
+	return (tangent, norm.cross(&tangent));
+}
+
+//Angle around axis mapped to [0, 1]
+fn angle_around(axis:&Vector3, radial:&Vector3) -> f64{
+	let (tangent, bitangent) = orthonormal_basis(axis);
+	return 0.5 + radial.dot(&bitangent).atan2(radial.dot(&tangent)) / (2.0 * std::f64::consts::PI);
+}
+
+//Distance along the ray to the plane through position with normal, f64::MAX if parallel or behind
+fn plane_distance(position:&Vector3, normal:&Vector3, ray:&Ray) -> f64{
+	let denominator = normal.dot(&ray.direction);
+	if denominator.abs() < 1e-12 {return f64::MAX;}
+
+	let distance = (position - ray.origin).dot(normal) / denominator;
+	if distance <= 0.0 {return f64::MAX;}
+	return distance;
+}
+
+impl Plane{
+	//Planar coordinates in world units, textures repeat over the infinite plane
+	pub fn uv_at(&self, point:&Vector3) -> (f64, f64){
+		let (tangent, bitangent) = orthonormal_basis(&self.normal);
+		let local = point - self.position;
+		return (local.dot(&tangent), local.dot(&bitangent));
+	}
+
+	pub fn intersection_distance(&self, ray:&Ray) -> f64{
+		return plane_distance(&self.position, &self.normal, ray);
+	}
+
+	pub fn get_hit_data<'a>(&self, object:&'a Object, distance:f64, ray:&Ray) -> Hit<'a>{
+		let point = ray.origin + distance * ray.direction;
+
+		//Ray started behind the plane, inside the solid half space
+		let inside = (ray.origin - self.position).dot(&self.normal) < 0.0;
+		let (tangent, bitangent) = orthonormal_basis(&self.normal);
+
+		return Hit::Something(HitData{
+			point:point,
+			norm:self.normal,
+			geometric_norm:self.normal,
+			inside:inside,
+			distance:distance,
+			object:object,
+			uv:self.uv_at(&point),
+			tangent,
+			bitangent,
+		});
+	}
+}
+
+impl Quad{
+	pub fn bounds(&self) -> Aabb{
+		let mut bounds = Aabb::empty();
+		bounds.grow(&self.position);
+		bounds.grow(&(self.position + self.u));
+		bounds.grow(&(self.position + self.v));
+		bounds.grow(&(self.position + self.u + self.v));
+
+		//Axis aligned quads have flat bounds
+		let padding = Vector3::new(1e-6, 1e-6, 1e-6);
+		return Aabb::new(bounds.min - padding, bounds.max + padding);
+	}
+
+	//Coordinates of a point on the quad plane along the u and v edges
+	fn planar_coordinates(&self, point:&Vector3) -> (f64, f64){
+		let n = self.u.cross(&self.v);
+		let w = 1.0 / n.norm_squared() * n;
+		let planar = point - self.position;
+		return (w.dot(&planar.cross(&self.v)), w.dot(&self.u.cross(&planar)));
+	}
+
+	pub fn intersection_distance(&self, ray:&Ray) -> f64{
+		let normal = self.normal();
+		let distance = plane_distance(&self.position, &normal, ray);
+		if distance == f64::MAX {return f64::MAX;}
+
+		let (alpha, beta) = self.planar_coordinates(&(ray.origin + distance * ray.direction));
+		if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {return f64::MAX;}
+
+		return distance;
+	}
+
+	pub fn get_hit_data<'a>(&self, object:&'a Object, distance:f64, ray:&Ray) -> Hit<'a>{
+		let point = ray.origin + distance * ray.direction;
+		let norm = self.normal();
+
+		//Quads have no volume, hitting the back face counts as leaving the object
+		let inside = ray.direction.dot(&norm) > 0.0;
+		let (tangent, bitangent) = tangent_frame(&norm, &self.u);
+
+		return Hit::Something(HitData{
+			point:point,
+			norm:norm,
+			geometric_norm:norm,
+			inside:inside,
+			distance:distance,
+			object:object,
+			uv:self.planar_coordinates(&point),
+			tangent,
+			bitangent,
+		});
+	}
+}
+
+//Visible crossing of a convex solid given its entry and exit distances, the exit when the ray starts inside
+fn convex_distance(t_near:f64, t_far:f64) -> f64{
+	if t_near > t_far {return f64::MAX;}
+	if t_near > 0.0 {return t_near;}
+	if t_far > 0.0 {return t_far;}
+	return f64::MAX;
+}
+
+//Real roots of a*t^2 + b*t + c in increasing order
+fn solve_quadratic(a:f64, b:f64, c:f64) -> Option<(f64, f64)>{
+	let discriminant = b * b - 4.0 * a * c;
+	if discriminant < 0.0 {return None;}
+
+	//Numerically stable form
+	let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+	let mut t0 = q / a;
+	let mut t1 = if q != 0.0 {c / q} else {t0};
+	if t0 > t1 {std::mem::swap(&mut t0, &mut t1);}
+	return Some((t0, t1));
+}
+
+fn sphere_roots(center:&Vector3, radius:f64, ray:&Ray) -> Option<(f64, f64)>{
+	let oc = ray.origin - *center;
+	return solve_quadratic(ray.direction.norm_squared(), 2.0 * oc.dot(&ray.direction), oc.norm_squared() - radius * radius);
+}
+
+//Bounds of a disk, each axis extends radius * sin of its angle with the normal
+fn disk_bounds(center:&Vector3, normal:&Vector3, radius:f64) -> Aabb{
+	let e = Vector3::new(
+		radius * (1.0 - normal.x * normal.x).max(0.0).sqrt(),
+		radius * (1.0 - normal.y * normal.y).max(0.0).sqrt(),
+		radius * (1.0 - normal.z * normal.z).max(0.0).sqrt());
+	return Aabb::new(center - e, center + e);
+}
+
+//Surface crossings of a convex solid, only the nearest and farthest ones matter
+struct Crossings{
+	t_near:f64,
+	t_far:f64,
+}
+
+impl Crossings{
+	fn new() -> Crossings{
+		return Crossings{t_near:f64::MAX, t_far:f64::MIN};
+	}
+
+	fn add(&mut self, t:f64){
+		self.t_near = self.t_near.min(t);
+		self.t_far = self.t_far.max(t);
+	}
+
+	fn distance(&self) -> f64{
+		return convex_distance(self.t_near, self.t_far);
+	}
+}
+
+impl Cuboid{
+	//Each face is mapped to the whole [0, 1] square
+	pub fn uv_at(&self, point:&Vector3) -> (f64, f64){
+		let normal = self.normal_at(point);
+		let size = self.max - self.min;
+		let local = point - self.min;
+		let relative = Vector3::new(local.x / size.x, local.y / size.y, local.z / size.z);
+
+		if normal.x != 0.0 {return (relative.z, relative.y);}
+		if normal.y != 0.0 {return (relative.x, relative.z);}
+		return (relative.x, relative.y);
+	}
+
+	pub fn tangent_at(&self, point:&Vector3) -> Vector3{
+		if self.normal_at(point).x != 0.0 {return Vector3::new(0.0, 0.0, 1.0);}
+		return Vector3::new(1.0, 0.0, 0.0);
+	}
+
+	pub fn intersection_distance(&self, ray:&Ray) -> f64{
+		let inv_direction = Vector3::new(1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z);
+		let mut t_near = f64::MIN;
+		let mut t_far = f64::MAX;
+
+		for axis in 0..3{
+			let t1 = (self.min.component(axis) - ray.origin.component(axis)) * inv_direction.component(axis);
+			let t2 = (self.max.component(axis) - ray.origin.component(axis)) * inv_direction.component(axis);
+			t_near = t_near.max(t1.min(t2));
+			t_far = t_far.min(t1.max(t2));
+		}
+
+		return convex_distance(t_near, t_far);
+	}
+
+	pub fn normal_at(&self, point:&Vector3) -> Vector3{
+		let center = 0.5 * (self.min + self.max);
+		let half_size = 0.5 * (self.max - self.min);
+		let local = point - center;
+
+		//The face whose plane is closest to the point
+		let mut best_axis = 0;
+		let mut best_distance = f64::MAX;
+		for axis in 0..3{
+			let distance = (half_size.component(axis) - local.component(axis).abs()).abs();
+			if distance < best_distance{
+				best_distance = distance;
+				best_axis = axis;
+			}
+		}
+
+		let sign = local.component(best_axis).signum();
+		return match best_axis{
+			0 => Vector3::new(sign, 0.0, 0.0),
+			1 => Vector3::new(0.0, sign, 0.0),
+			_ => Vector3::new(0.0, 0.0, sign),
+		};
+	}
+
+	pub fn area(&self) -> f64{
+		let size = self.max - self.min;
+		return 2.0 * (size.y * size.z + size.z * size.x + size.x * size.y);
+	}
+
+	//Uniform point, u.0 picks a face by area and is then reused along it
+	pub fn sample_point(&self, u:(f64, f64)) -> Vector3{
+		let size = self.max - self.min;
+		let faces = [size.y * size.z, size.z * size.x, size.x * size.y];
+
+		let mut u1 = u.0 * self.area();
+		let mut axis = 0;
+		while axis < 2 && u1 >= 2.0 * faces[axis]{
+			u1 -= 2.0 * faces[axis];
+			axis += 1;
+		}
+
+		//The first half of the share of an axis is its min face
+		let t = (u1 / faces[axis]).min(2.0);
+		let (side, along) = if t < 1.0 {(0.0, t)} else {(1.0, t - 1.0)};
+		let mut local = [0.0; 3];
+		local[axis] = side;
+		local[(axis + 1) % 3] = along;
+		local[(axis + 2) % 3] = u.1;
+		return self.min + Vector3::new(local[0], local[1], local[2]).mult(&size);
+	}
+}
+
+impl Cylinder{
+	//Angle around the axis and height, caps use the same angle and the distance to the axis
+	pub fn uv_at(&self, point:&Vector3) -> (f64, f64){
+		let local = point - self.base;
+		let h = local.dot(&self.axis);
+		let radial = local - h * self.axis;
+		return (angle_around(&self.axis, &radial), (h / self.height).clamp(0.0, 1.0));
+	}
+
+	pub fn bounds(&self) -> Aabb{
+		let bottom = disk_bounds(&self.base, &self.axis, self.radius);
+		let top = disk_bounds(&(self.base + self.height * self.axis), &self.axis, self.radius);
+		return bottom.union(&top);
+	}
+
+	pub fn intersection_distance(&self, ray:&Ray) -> f64{
+		let oc = ray.origin - self.base;
+		let d_axis = ray.direction.dot(&self.axis);
+		let o_axis = oc.dot(&self.axis);
+
+		//Slab between the caps
+		let mut t_near;
+		let mut t_far;
+		if d_axis.abs() < 1e-12{
+			if o_axis < 0.0 || o_axis > self.height {return f64::MAX;}
+			t_near = f64::MIN;
+			t_far = f64::MAX;
+		}else{
+			let t1 = -o_axis / d_axis;
+			let t2 = (self.height - o_axis) / d_axis;
+			t_near = t1.min(t2);
+			t_far = t1.max(t2);
+		}
+
+		//Infinite side
+		let d_perp = ray.direction - d_axis * self.axis;
+		let o_perp = oc - o_axis * self.axis;
+		let a = d_perp.norm_squared();
+		let c = o_perp.norm_squared() - self.radius * self.radius;
+
+		if a < 1e-12{
+			//Parallel to the axis
+			if c > 0.0 {return f64::MAX;}
+		}else{
+			match solve_quadratic(a, 2.0 * d_perp.dot(&o_perp), c){
+				None => {return f64::MAX;},
+				Some((t0, t1)) => {
+					t_near = t_near.max(t0);
+					t_far = t_far.min(t1);
+				},
+			}
+		}
+
+		return convex_distance(t_near, t_far);
+	}
+
+	pub fn normal_at(&self, point:&Vector3) -> Vector3{
+		let local = point - self.base;
+		let h = local.dot(&self.axis);
+		let radial = local - h * self.axis;
+		let radial_distance = radial.norm();
+
+		let side_distance = (radial_distance - self.radius).abs();
+		if h.abs() < side_distance {return -self.axis;}
+		if (self.height - h).abs() < side_distance {return self.axis;}
+		return 1.0 / radial_distance * radial;
+	}
+
+	pub fn area(&self) -> f64{
+		return 2.0 * std::f64::consts::PI * self.radius * (self.height + self.radius);
+	}
+
+	//Uniform point, u.0 picks the side or a cap by area and is then reused along it
+	pub fn sample_point(&self, u:(f64, f64)) -> Vector3{
+		let (tangent, bitangent) = orthonormal_basis(&self.axis);
+		let side = 2.0 * std::f64::consts::PI * self.radius * self.height;
+		let cap = std::f64::consts::PI * self.radius * self.radius;
+
+		let mut u1 = u.0 * self.area();
+		if u1 < side{
+			let phi = 2.0 * std::f64::consts::PI * u.1;
+			return self.base + (u1 / side * self.height) * self.axis + self.radius * (phi.cos() * tangent + phi.sin() * bitangent);
+		}
+		u1 -= side;
+
+		let mut h = 0.0;
+		if u1 >= cap{
+			u1 -= cap;
+			h = self.height;
+		}
+		let ((x, y), _) = sampling::uniform_disk((u1 / cap).min(1.0), u.1);
+		return self.base + h * self.axis + self.radius * (x * tangent + y * bitangent);
+	}
+}
+
+impl Cone{
+	pub fn uv_at(&self, point:&Vector3) -> (f64, f64){
+		let local = point - self.base;
+		let h = local.dot(&self.axis);
+		let radial = local - h * self.axis;
+		return (angle_around(&self.axis, &radial), (h / self.height).clamp(0.0, 1.0));
+	}
+
+	pub fn bounds(&self) -> Aabb{
+		let mut bounds = disk_bounds(&self.base, &self.axis, self.radius);
+		bounds.grow(&self.apex());
+		return bounds;
+	}
+
+	pub fn intersection_distance(&self, ray:&Ray) -> f64{
+		let oc = ray.origin - self.base;
+		let d_axis = ray.direction.dot(&self.axis);
+		let o_axis = oc.dot(&self.axis);
+		let d_perp = ray.direction - d_axis * self.axis;
+		let o_perp = oc - o_axis * self.axis;
+
+		let mut crossings = Crossings::new();
+
+		//Side, |perp|^2 = k^2 (height - h)^2
+		let k = self.radius / self.height;
+		let k2 = k * k;
+		let remaining = self.height - o_axis;
+		let a = d_perp.norm_squared() - k2 * d_axis * d_axis;
+		let b = 2.0 * (d_perp.dot(&o_perp) + k2 * remaining * d_axis);
+		let c = o_perp.norm_squared() - k2 * remaining * remaining;
+
+		let mut side_hit = |t:f64| {
+			let h = o_axis + t * d_axis;
+			if h >= 0.0 && h <= self.height{
+				crossings.add(t);
+			}
+		};
+
+		if a.abs() < 1e-12{
+			if b.abs() > 1e-12 {side_hit(-c / b);}
+		}else if let Some((t0, t1)) = solve_quadratic(a, b, c){
+			side_hit(t0);
+			side_hit(t1);
+		}
+
+		//Base cap
+		if d_axis.abs() > 1e-12{
+			let t = -o_axis / d_axis;
+			if (o_perp + t * d_perp).norm_squared() <= self.radius * self.radius{
+				crossings.add(t);
+			}
+		}
+
+		return crossings.distance();
+	}
+
+	pub fn normal_at(&self, point:&Vector3) -> Vector3{
+		let local = point - self.base;
+		let h = local.dot(&self.axis);
+		let radial = local - h * self.axis;
+		let radial_distance = radial.norm();
+
+		let k = self.radius / self.height;
+		let side_distance = (radial_distance - k * (self.height - h)).abs() / (1.0 + k * k).sqrt();
+		if h.abs() < side_distance {return -self.axis;}
+		if radial_distance <= 0.0 {return self.axis;}
+		return (1.0 / radial_distance * radial + k * self.axis).normalize();
+	}
+
+	pub fn area(&self) -> f64{
+		let slant = (self.radius * self.radius + self.height * self.height).sqrt();
+		return std::f64::consts::PI * self.radius * (slant + self.radius);
+	}
+
+	//Uniform point, u.0 picks the side or the base by area and is then reused along it
+	pub fn sample_point(&self, u:(f64, f64)) -> Vector3{
+		let (tangent, bitangent) = orthonormal_basis(&self.axis);
+		let base = std::f64::consts::PI * self.radius * self.radius;
+
+		let u1 = u.0 * self.area();
+		if u1 < base{
+			let ((x, y), _) = sampling::uniform_disk(u1 / base, u.1);
+			return self.base + self.radius * (x * tangent + y * bitangent);
+		}
+
+		//The side grows linearly away from the apex
+		let side = self.area() - base;
+		let s = ((u1 - base) / side).min(1.0).sqrt();
+		let phi = 2.0 * std::f64::consts::PI * u.1;
+		let rim = self.base + self.radius * (phi.cos() * tangent + phi.sin() * bitangent);
+		let apex = self.apex();
+		return apex + s * (rim - apex);
+	}
+}
+
+impl Disk{
+	//Planar mapping of the disk square to [0, 1]
+	pub fn uv_at(&self, point:&Vector3) -> (f64, f64){
+		let (tangent, bitangent) = orthonormal_basis(&self.normal);
+		let local = 1.0 / self.radius * (point - self.position);
+		return (0.5 + 0.5 * local.dot(&tangent), 0.5 + 0.5 * local.dot(&bitangent));
+	}
+
+	pub fn intersection_distance(&self, ray:&Ray) -> f64{
+		let distance = plane_distance(&self.position, &self.normal, ray);
+		if distance == f64::MAX {return f64::MAX;}
+
+		let point = ray.origin + distance * ray.direction;
+		if (point - self.position).norm_squared() > self.radius * self.radius {return f64::MAX;}
+		return distance;
+	}
+}
+
+impl Capsule{
+	//Unit direction and length of the segment, a capsule with a == b is a sphere and any axis does
+	fn axis(&self) -> (Vector3, f64){
+		let segment = self.b - self.a;
+		let length = segment.norm();
+		if length <= 0.0 {return (Vector3::new(0.0, 1.0, 0.0), 0.0);}
+		return (1.0 / length * segment, length);
+	}
+
+	//Angle around the segment and position along the whole capsule, hemispheres included
+	pub fn uv_at(&self, point:&Vector3) -> (f64, f64){
+		let (axis, length) = self.axis();
+		let local = point - self.a;
+		let h = local.dot(&axis);
+		let radial = local - h * axis;
+		return (angle_around(&axis, &radial), ((h + self.radius) / (length + 2.0 * self.radius)).clamp(0.0, 1.0));
+	}
+
+	pub fn bounds(&self) -> Aabb{
+		let r = Vector3::new(self.radius, self.radius, self.radius);
+		let mut bounds = Aabb::new(self.a - r, self.a + r);
+		bounds = bounds.union(&Aabb::new(self.b - r, self.b + r));
+		return bounds;
+	}
+
+	pub fn intersection_distance(&self, ray:&Ray) -> f64{
+		let (axis, length) = self.axis();
+
+		let oc = ray.origin - self.a;
+		let d_axis = ray.direction.dot(&axis);
+		let o_axis = oc.dot(&axis);
+		let h = |t:f64| o_axis + t * d_axis;
+
+		let mut crossings = Crossings::new();
+
+		//Cylinder body between the two hemispheres
+		let d_perp = ray.direction - d_axis * axis;
+		let o_perp = oc - o_axis * axis;
+		if let Some((t0, t1)) = solve_quadratic(d_perp.norm_squared(), 2.0 * d_perp.dot(&o_perp), o_perp.norm_squared() - self.radius * self.radius){
+			for t in [t0, t1]{
+				if h(t) >= 0.0 && h(t) <= length {crossings.add(t);}
+			}
+		}
+
+		//Hemispheres
+		if let Some((t0, t1)) = sphere_roots(&self.a, self.radius, ray){
+			for t in [t0, t1]{
+				if h(t) < 0.0 {crossings.add(t);}
+			}
+		}
+		if let Some((t0, t1)) = sphere_roots(&self.b, self.radius, ray){
+			for t in [t0, t1]{
+				if h(t) > length {crossings.add(t);}
+			}
+		}
+
+		return crossings.distance();
+	}
+
+	pub fn normal_at(&self, point:&Vector3) -> Vector3{
+		let (axis, length) = self.axis();
+		let h = (point - self.a).dot(&axis).clamp(0.0, length);
+		let closest = self.a + h * axis;
+		return (point - closest).normalize();
+	}
+
+	pub fn area(&self) -> f64{
+		let length = (self.b - self.a).norm();
+		return 2.0 * std::f64::consts::PI * self.radius * (length + 2.0 * self.radius);
+	}
+
+	//Uniform point, u.0 picks the side or the hemispheres by area and is then reused
+	pub fn sample_point(&self, u:(f64, f64)) -> Vector3{
+		let segment = self.b - self.a;
+		let side = 2.0 * std::f64::consts::PI * self.radius * segment.norm();
+
+		let u1 = u.0 * self.area();
+		if u1 < side{
+			let axis = segment.normalize();
+			let (tangent, bitangent) = orthonormal_basis(&axis);
+			let phi = 2.0 * std::f64::consts::PI * u.1;
+			return self.a + (u1 / side) * segment + self.radius * (phi.cos() * tangent + phi.sin() * bitangent);
+		}
+
+		//Both hemispheres together make a whole sphere
+		let (direction, _) = sampling::uniform_sphere(((u1 - side) / (self.area() - side)).min(1.0), u.1);
+		let center = if direction.dot(&segment) >= 0.0 {self.b} else {self.a};
+		return center + self.radius * direction;
+	}
+}
+
+impl Instance{
+	//Ray in object space, the direction is renormalized so the returned scale converts distances
+	fn local_ray(&self, ray:&Ray) -> (Ray, f64){
+		let direction = self.transform.vector_to_object(&ray.direction);
+		let scale = direction.norm();
+
+		return (Ray{
+			origin:self.transform.point_to_object(&ray.origin),
+			direction:1.0 / scale * direction,
+			wavelengths:ray.wavelengths,
+		}, scale);
+	}
+
+	pub fn bounds(&self) -> Aabb{
+		let local_bounds = self.shape.bounds();
+		if !local_bounds.is_finite() {return local_bounds;}
+
+		let mut bounds = Aabb::empty();
+		for i in 0..8{
+			let corner = Vector3::new(
+				if i & 1 == 0 {local_bounds.min.x} else {local_bounds.max.x},
+				if i & 2 == 0 {local_bounds.min.y} else {local_bounds.max.y},
+				if i & 4 == 0 {local_bounds.min.z} else {local_bounds.max.z});
+			bounds.grow(&self.transform.point_to_world(&corner));
+		}
+		return bounds;
+	}
+
+	pub fn intersection_distance(&self, ray:&Ray, max_distance:f64) -> ShapeHit{
+		let (local_ray, scale) = self.local_ray(ray);
+		let mut shape_hit = self.shape.intersection_distance(&local_ray, max_distance * scale);
+
+		if shape_hit.distance != f64::MAX{
+			shape_hit.distance /= scale;
+		}
+		return shape_hit;
+	}
+
+	pub fn get_hit_data<'a>(&self, object:&'a Object, shape_hit:&ShapeHit, ray:&Ray) -> Hit<'a>{
+		let (local_ray, scale) = self.local_ray(ray);
+		let local_hit = ShapeHit{distance:shape_hit.distance * scale, ..*shape_hit};
+
+		match self.shape.get_hit_data(object, &local_hit, &local_ray){
+			Hit::Nothing => {
+				return Hit::Nothing;
+			},
+			Hit::Something(mut hit_data) => {
+				//The inside flag is the same in both spaces, only point, normal and distance move
+				hit_data.point = ray.origin + shape_hit.distance * ray.direction;
+				hit_data.norm = self.transform.normal_to_world(&hit_data.norm);
+				hit_data.geometric_norm = self.transform.normal_to_world(&hit_data.geometric_norm);
+				(hit_data.tangent, hit_data.bitangent) = tangent_frame(&hit_data.norm, &self.transform.vector_to_world(&hit_data.tangent));
+				hit_data.distance = shape_hit.distance;
+				return Hit::Something(hit_data);
+			},
+		}
+	}
+
+	//Factor object space area around local_normal grows by in world space
+	fn area_scale(&self, local_normal:&Vector3) -> f64{
+		let (tangent, bitangent) = orthonormal_basis(local_normal);
+		return self.transform.vector_to_world(&tangent).cross(&self.transform.vector_to_world(&bitangent)).norm();
+	}
+
+	pub fn sample_area(&self, u:(f64, f64)) -> Option<(Vector3, Vector3, f64)>{
+		let (point, normal, pdf) = self.shape.sample_area(u)?;
+		return Some((self.transform.point_to_world(&point), self.transform.normal_to_world(&normal), pdf / self.area_scale(&normal)));
+	}
+
+	pub fn area_pdf(&self, point:&Vector3, normal:&Vector3) -> f64{
+		let local_normal = self.transform.normal_to_object(normal);
+		return self.shape.area_pdf(&self.transform.point_to_object(point), &local_normal) / self.area_scale(&local_normal);
+	}
+}
+
+//Ray projected so its direction is the +z axis, see Woop et al. "Watertight Ray/Triangle Intersection"
+struct WatertightRay{
+	kx:usize,
+	ky:usize,
+	kz:usize,
+	sx:f64,
+	sy:f64,
+	sz:f64,
+}
+
+impl WatertightRay{
+	fn new(direction:&Vector3) -> WatertightRay{
+		let abs = Vector3::new(direction.x.abs(), direction.y.abs(), direction.z.abs());
+		let kz = if abs.x > abs.y && abs.x > abs.z {0} else if abs.y > abs.z {1} else {2};
+		let mut kx = (kz + 1) % 3;
+		let mut ky = (kx + 1) % 3;
+
+		//Keep winding direction
+		if direction.component(kz) < 0.0{
+			std::mem::swap(&mut kx, &mut ky);
 		}
+
+		let dz = direction.component(kz);
+		return WatertightRay{
+			kx,
+			ky,
+			kz,
+			sx:direction.component(kx) / dz,
+			sy:direction.component(ky) / dz,
+			sz:1.0 / dz,
+		};
+	}
+
+	//Returns the distance and the barycentric weights of p1 and p2
+	fn intersect(&self, origin:&Vector3, p0:&Vector3, p1:&Vector3, p2:&Vector3, max_distance:f64) -> Option<(f64, f64, f64)>{
+		let a = p0 - *origin;
+		let b = p1 - *origin;
+		let c = p2 - *origin;
+
+		let ax = a.component(self.kx) - self.sx * a.component(self.kz);
+		let ay = a.component(self.ky) - self.sy * a.component(self.kz);
+		let bx = b.component(self.kx) - self.sx * b.component(self.kz);
+		let by = b.component(self.ky) - self.sy * b.component(self.kz);
+		let cx = c.component(self.kx) - self.sx * c.component(self.kz);
+		let cy = c.component(self.ky) - self.sy * c.component(self.kz);
+
+		let u = cx * by - cy * bx;
+		let v = ax * cy - ay * cx;
+		let w = bx * ay - by * ax;
+
+		if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {return None;}
+		if (u == 0.0 && !owns_edge((bx, by), (cx, cy), (ax, ay)))
+			|| (v == 0.0 && !owns_edge((cx, cy), (ax, ay), (bx, by)))
+			|| (w == 0.0 && !owns_edge((ax, ay), (bx, by), (cx, cy))) {return None;}
+
+		let det = u + v + w;
+		if det == 0.0 {return None;}
+
+		let az = self.sz * a.component(self.kz);
+		let bz = self.sz * b.component(self.kz);
+		let cz = self.sz * c.component(self.kz);
+		let t = u * az + v * bz + w * cz;
+
+		if det < 0.0 && (t >= 0.0 || t < max_distance * det) {return None;}
+		if det > 0.0 && (t <= 0.0 || t > max_distance * det) {return None;}
+
+		let inv_det = 1.0 / det;
+		return Some((t * inv_det, v * inv_det, w * inv_det));
 	}
 }
 
+//Rays exactly on an edge go to the triangle left of the edge taken in a fixed order, the triangle on the
+//other side of a shared edge is then right of it. Rays through shared edges and vertices hit one triangle.
+fn owns_edge(p:(f64, f64), q:(f64, f64), opposite:(f64, f64)) -> bool{
+	let (start, end) = if p < q {(p, q)} else {(q, p)};
+	let side = (end.0 - start.0) * (opposite.1 - start.1) - (end.1 - start.1) * (opposite.0 - start.0);
+	return side > 0.0;
+}
+
+impl Mesh{
+	pub fn intersection_distance(&self, ray:&Ray, max_distance:f64) -> ShapeHit{
+		let watertight_ray = WatertightRay::new(&ray.direction);
+		let mut closest_hit = ShapeHit::NOTHING;
+
+		self.bvh.intersect(ray, max_distance, |i, max_distance| {
+			let triangle = self.triangles[i];
+			let p0 = &self.positions[triangle[0] as usize];
+			let p1 = &self.positions[triangle[1] as usize];
+			let p2 = &self.positions[triangle[2] as usize];
+
+			if let Some((distance, u, v)) = watertight_ray.intersect(&ray.origin, p0, p1, p2, max_distance){
+				if distance >= max_distance {return f64::MAX;}
+				closest_hit = ShapeHit{distance, primitive:i as u32, u, v};
+				return distance;
+			}
+			return f64::MAX;
+		});
+
+		return closest_hit;
+	}
+
+	pub fn get_hit_data<'a>(&self, object:&'a Object, shape_hit:&ShapeHit, ray:&Ray) -> Hit<'a>{
+		let triangle = self.triangles[shape_hit.primitive as usize];
+		let p0 = self.positions[triangle[0] as usize];
+		let p1 = self.positions[triangle[1] as usize];
+		let p2 = self.positions[triangle[2] as usize];
+
+		let point = ray.origin + shape_hit.distance * ray.direction;
+		let geometric_norm = (p1 - p0).cross(&(p2 - p0)).normalize();
+
+		//Triangles have no volume, hitting the back face means leaving the mesh
+		let inside = ray.direction.dot(&geometric_norm) > 0.0;
+
+		let w = 1.0 - shape_hit.u - shape_hit.v;
+		let mut uv = (shape_hit.u, shape_hit.v);
+		let mut dpdu = p1 - p0;
+		if self.has_uvs(){
+			let uv0 = self.uvs[triangle[0] as usize];
+			let uv1 = self.uvs[triangle[1] as usize];
+			let uv2 = self.uvs[triangle[2] as usize];
+			uv = (w * uv0.0 + shape_hit.u * uv1.0 + shape_hit.v * uv2.0, w * uv0.1 + shape_hit.u * uv1.1 + shape_hit.v * uv2.1);
+
+			//Solve the edges for the derivative of the position along u
+			let (du1, dv1) = (uv1.0 - uv0.0, uv1.1 - uv0.1);
+			let (du2, dv2) = (uv2.0 - uv0.0, uv2.1 - uv0.1);
+			let determinant = du1 * dv2 - du2 * dv1;
+			if determinant.abs() > 1e-12{
+				dpdu = 1.0 / determinant * (dv2 * (p1 - p0) - dv1 * (p2 - p0));
+			}
+		}
+
+		let mut norm = geometric_norm;
+		if self.has_normals(){
+			let n0 = self.normals[triangle[0] as usize];
+			let n1 = self.normals[triangle[1] as usize];
+			let n2 = self.normals[triangle[2] as usize];
+			let interpolated = w * n0 + shape_hit.u * n1 + shape_hit.v * n2;
+
+			if interpolated.norm_squared() > 0.0{
+				norm = interpolated.normalize();
+				//Shading normal must stay on the geometric side
+				if norm.dot(&geometric_norm) < 0.0{
+					norm = -norm;
+				}
+			}
+		}
+
+		let (tangent, bitangent) = tangent_frame(&norm, &dpdu);
+
+		return Hit::Something(HitData{
+			point:point,
+			norm:norm,
+			geometric_norm:geometric_norm,
+			inside:inside,
+			distance:shape_hit.distance,
+			object:object,
+			uv,
+			tangent,
+			bitangent,
+		});
+	}
+
+	//Uniform point on the triangles, u.0 picks a triangle by area and is then reused on it
+	pub fn sample_area(&self, u:(f64, f64)) -> (Vector3, Vector3, f64){
+		let area = self.area();
+		let target = u.0 * area;
+		let i = self.area_cdf.partition_point(|&sum| sum <= target).min(self.triangles.len() - 1);
+		let start = if i == 0 {0.0} else {self.area_cdf[i - 1]};
+		let u1 = ((target - start) / (self.area_cdf[i] - start)).clamp(0.0, 1.0);
+
+		let triangle = self.triangles[i];
+		let p0 = &self.positions[triangle[0] as usize];
+		let p1 = &self.positions[triangle[1] as usize];
+		let p2 = &self.positions[triangle[2] as usize];
+		let (point, _, _) = sampling::uniform_triangle(p0, p1, p2, u1, u.1);
+		return (point, (p1 - *p0).cross(&(p2 - *p0)).normalize(), 1.0 / area);
+	}
+}
 
 impl Sphere{
+	//Longitude and latitude, v goes from the bottom pole to the top one
+	pub fn uv_at(&self, point:&Vector3) -> (f64, f64){
+		let n = 1.0 / self.radius * (point - self.position);
+		let theta = (-n.y).clamp(-1.0, 1.0).acos();
+		let phi = (-n.z).atan2(n.x) + std::f64::consts::PI;
+		return (phi / (2.0 * std::f64::consts::PI), theta / std::f64::consts::PI);
+	}
+
+	//Along the parallels, in the direction of increasing longitude
+	pub fn tangent_at(&self, point:&Vector3) -> Vector3{
+		let n = point - self.position;
+		return Vector3::new(n.z, 0.0, -n.x);
+	}
+
+	pub fn bounds(&self) -> Aabb{
+		let r = Vector3::new(self.radius, self.radius, self.radius);
+		return Aabb::new(self.position - r, self.position + r);
+	}
+
 	pub fn intersection<'a>(&self, object:&'a Object, ray:&Ray) -> Hit<'a>{
 			let origin_to_center = self.position - ray.origin;
 			let proj_length = origin_to_center.dot(&ray.direction);
@@ -119,13 +1160,18 @@ impl Sphere{
 				//Distance from camera and surface norm
 				let point = ray.origin + distance * ray.direction;
 				let norm = (point - self.position).normalize();
+				let (tangent, bitangent) = tangent_frame(&norm, &self.tangent_at(&point));
 
 				return Hit::Something(HitData{
 					point:point,
 					norm:norm,
+					geometric_norm:norm,
 					inside:inside,
 					distance:distance,
 					object:object,
+					uv:self.uv_at(&point),
+					tangent,
+					bitangent,
 				});
 			}
 
@@ -176,13 +1222,129 @@ impl Sphere{
 		let to_origin = ray.origin - self.position;
 		let proj = to_origin.dot(&to_origin);
 		let inside = proj < self.radius * self.radius;
+		let (tangent, bitangent) = tangent_frame(&norm, &self.tangent_at(&point));
 
 		return Hit::Something(HitData{
 			point:point,
 			norm:norm,
+			geometric_norm:norm,
 			inside:inside,
 			distance:distance,
 			object:object,
+			uv:self.uv_at(&point),
+			tangent,
+			bitangent,
 		});
 	}
+
+	//Cosine of the half angle of the cone the sphere covers seen from reference. None from inside and from
+	//so far away that the cosine rounds to 1, area sampling works there instead.
+	pub fn cone_cos_max(&self, reference:&Vector3) -> Option<f64>{
+		let square_distance = (self.position - *reference).norm_squared();
+		let square_radius = self.radius * self.radius;
+		if square_distance <= square_radius || square_radius < 1e-8 * square_distance {return None;}
+		return Some((1.0 - square_radius / square_distance).sqrt());
+	}
+
+	//Visible point in a uniform direction of the cone the sphere covers, None when cone_cos_max is
+	//None, i.e. the reference is inside the sphere or the sphere is negligibly small
+	pub fn sample_cone(&self, reference:&Vector3, u:(f64, f64)) -> Option<Vector3>{
+		let cos_max = self.cone_cos_max(reference)?;
+		let to_center = self.position - *reference;
+		let (local, _) = sampling::uniform_cone(cos_max, u.0, u.1);
+		let direction = sampling::to_basis(&to_center.normalize(), &local);
+
+		//Nearest intersection, directions grazing the silhouette land on the tangent point
+		let proj_length = to_center.dot(&direction);
+		let square_distance = (to_center - proj_length * direction).norm_squared();
+		let distance = proj_length - (self.radius * self.radius - square_distance).max(0.0).sqrt();
+		return Some(reference + distance * direction);
+	}
+}
+
+#[cfg(test)]
+mod tests{
+	use super::*;
+	use rand::{Rng, SeedableRng};
+	use rand::rngs::StdRng;
+
+	//Hits of a ray aimed at target over all triangles, from a random direction
+	fn hit_counts(triangles:&[[Vector3; 3]], targets:&[Vector3], rng:&mut StdRng){
+		for target in targets{
+			for _ in 0..500{
+				let direction = Vector3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(0.1..1.0)).normalize();
+				let origin = target - 5.0 * direction;
+				let watertight_ray = WatertightRay::new(&direction);
+
+				let hits = triangles.iter().filter(|t| watertight_ray.intersect(&origin, &t[0], &t[1], &t[2], f64::MAX).is_some()).count();
+				assert_eq!(hits, 1, "ray from {:?} to {:?}", origin, target);
+			}
+		}
+	}
+
+	#[test]
+	fn shared_edge_is_hit_once(){
+		let mut rng = StdRng::seed_from_u64(1);
+		let p = [Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 0.0), Vector3::new(0.0, 1.0, 0.0)];
+		let triangles = [[p[0], p[1], p[2]], [p[0], p[2], p[3]]];
+
+		let targets:Vec<Vector3> = [0.25, 0.5, 0.75, 1.0 / 3.0].iter().map(|t| *t * p[2]).collect();
+		hit_counts(&triangles, &targets, &mut rng);
+
+		//Straight down the projection is exact and the edge functions are exactly 0
+		let direction = Vector3::new(0.0, 0.0, -1.0);
+		let watertight_ray = WatertightRay::new(&direction);
+		let hits = triangles.iter().filter(|t| watertight_ray.intersect(&Vector3::new(0.5, 0.5, 1.0), &t[0], &t[1], &t[2], f64::MAX).is_some()).count();
+		assert_eq!(hits, 1);
+	}
+
+	#[test]
+	fn shared_vertex_is_hit_once(){
+		let mut rng = StdRng::seed_from_u64(2);
+		let center = Vector3::new(0.2, -0.3, 0.1);
+		let rim:Vec<Vector3> = (0..7).map(|i| {
+			let angle = i as f64 * 2.0 * std::f64::consts::PI / 7.0 + 0.1 * i as f64;
+			center + Vector3::new(angle.cos(), angle.sin(), 0.0)
+		}).collect();
+		let triangles:Vec<[Vector3; 3]> = (0..7).map(|i| [center, rim[i], rim[(i + 1) % 7]]).collect();
+
+		hit_counts(&triangles, &[center], &mut rng);
+
+		let watertight_ray = WatertightRay::new(&Vector3::new(0.0, 0.0, 1.0));
+		let hits = triangles.iter().filter(|t| watertight_ray.intersect(&(center - Vector3::new(0.0, 0.0, 1.0)), &t[0], &t[1], &t[2], f64::MAX).is_some()).count();
+		assert_eq!(hits, 1);
+	}
+
+	#[test]
+	fn capsule_with_equal_ends_is_a_sphere(){
+		let center = Vector3::new(1.0, 2.0, 3.0);
+		let capsule = Shape::Capsule(Capsule{a:center, b:center, radius:0.5});
+		let sphere = Shape::Sphere(Sphere::create_sphere(center, 0.5));
+		let mut rng = StdRng::seed_from_u64(3);
+
+		for _ in 0..200{
+			let direction = Vector3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)).normalize();
+			let ray = Ray{origin:center - 3.0 * direction + Vector3::new(rng.gen_range(-0.4..0.4), 0.0, 0.0), direction, wavelengths:None};
+			let distance = capsule.intersection_distance(&ray, f64::MAX).distance;
+			assert!((distance - sphere.intersection_distance(&ray, f64::MAX).distance).abs() < 1e-9);
+
+			let point = ray.origin + distance * ray.direction;
+			let normal = capsule.normal_at(&point);
+			let (u, v) = capsule.uv_at(&point);
+			assert!((normal.norm() - 1.0).abs() < 1e-9 && u.is_finite() && v.is_finite());
+		}
+	}
+
+	//Objects added after the bvh build would be missed, debug builds catch it instead
+	#[test]
+	#[cfg(debug_assertions)]
+	#[should_panic(expected = "call update_bvh")]
+	fn raycast_through_a_stale_bvh_panics(){
+		let camera = crate::scene::Camera::new(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), 60.0);
+		let mut scene = Scene::new(camera);
+		scene.build_bvh();
+		scene.create_sphere(Vector3::new(0.0, 0.0, 0.0), 1.0, &String::from("base"));
+		let ray = Ray{origin:Vector3::new(0.0, 0.0, 5.0), direction:Vector3::new(0.0, 0.0, -1.0), wavelengths:None};
+		raycast(&scene, &ray);
+	}
 }
\ No newline at end of file
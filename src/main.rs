@@ -7,9 +7,15 @@ mod render;
 mod intersection;
 mod animation;
 mod vector3;
+mod bvh;
+mod matrix4;
+mod spectrum;
+mod sampling;
+mod sampler;
 
 use std::time::Instant;
 use crate::vector3::Vector3;
+use crate::sampler::SamplerKind;
 
 fn main() {
     let window = window::Window{width:1920, height:1080,};
@@ -17,8 +23,9 @@ fn main() {
     let now = Instant::now();
 
     let raytracing_config = render::raytracing_config::RaytracingConfig{
-        exposure:1.0, gamma:2.2, rays_per_pixel:0, ray_bounce_max_depth:5, convergence_threshold:0.2, 
-        parallel:true, denoise:true, denoise_with_normals:true};
+        exposure:1.0, gamma:2.2, rays_per_pixel:0, ray_bounce_max_depth:5, convergence_threshold:0.2,
+        diffuse_max_depth:3, glossy_max_depth:4, transmission_max_depth:5, portal_max_depth:5, volume_max_depth:128, russian_roulette_depth:3,
+        parallel:true, denoise:true, denoise_with_normals:true, spectral:false, sampler:SamplerKind::Independent, seed:0};
 
     pub enum RenderOption{
         Image,
@@ -33,7 +40,7 @@ fn main() {
         RenderOption::Image =>{
             //Render image
             println!("Rendering image.");
-            let img = render::render(&main_scene, window.width, window.height, raytracing_config);
+            let img = render::render(&mut main_scene, window.width, window.height, raytracing_config);
             img.save("output.png").unwrap();
         },
         RenderOption::BlurAnimation =>{
@@ -47,8 +54,9 @@ fn main() {
         },
         RenderOption::MetalFuzzAnimation =>{
             //Metal fuzz animation
-            let obj_id = 8;
-            animation::render_metal_fuzz_animation(&window, raytracing_config, &mut main_scene, 10, 1, obj_id , 0.0, 0.0);
+            let obj = main_scene.handles().nth(8).unwrap();
+            let material = main_scene.object(obj).material;
+            animation::render_metal_fuzz_animation(&window, raytracing_config, &mut main_scene, 10, 1, material , 0.0, 0.0);
         },
         RenderOption::LightTunnelAnimation =>{
             let camera_starting_position = Vector3::new(0.0, 5.0, 0.0);
@@ -0,0 +1,236 @@
+#![allow(dead_code)]
+
+use std::ops;
+use crate::vector3::Vector3;
+
+//Row major 4x4 matrix, vectors are columns so matrix * point applies the transform
+#[derive(Debug, Clone, Copy)]
+pub struct Matrix4{
+	pub m:[[f64; 4]; 4],
+}
+
+impl Matrix4{
+	pub const fn new(m:[[f64; 4]; 4]) -> Matrix4{
+		Matrix4{
+			m,
+		}
+	}
+
+	pub const fn identity() -> Matrix4{
+		Matrix4::new([
+			[1.0, 0.0, 0.0, 0.0],
+			[0.0, 1.0, 0.0, 0.0],
+			[0.0, 0.0, 1.0, 0.0],
+			[0.0, 0.0, 0.0, 1.0],
+		])
+	}
+
+	pub fn translation(t:&Vector3) -> Matrix4{
+		Matrix4::new([
+			[1.0, 0.0, 0.0, t.x],
+			[0.0, 1.0, 0.0, t.y],
+			[0.0, 0.0, 1.0, t.z],
+			[0.0, 0.0, 0.0, 1.0],
+		])
+	}
+
+	pub fn scale(s:&Vector3) -> Matrix4{
+		Matrix4::new([
+			[s.x, 0.0, 0.0, 0.0],
+			[0.0, s.y, 0.0, 0.0],
+			[0.0, 0.0, s.z, 0.0],
+			[0.0, 0.0, 0.0, 1.0],
+		])
+	}
+
+	//Rotation of angle radians around axis, right handed
+	pub fn rotation(axis:&Vector3, angle:f64) -> Matrix4{
+		let a = axis.normalize();
+		let (sin, cos) = angle.sin_cos();
+		let t = 1.0 - cos;
+
+		Matrix4::new([
+			[t * a.x * a.x + cos, t * a.x * a.y - sin * a.z, t * a.x * a.z + sin * a.y, 0.0],
+			[t * a.x * a.y + sin * a.z, t * a.y * a.y + cos, t * a.y * a.z - sin * a.x, 0.0],
+			[t * a.x * a.z - sin * a.y, t * a.y * a.z + sin * a.x, t * a.z * a.z + cos, 0.0],
+			[0.0, 0.0, 0.0, 1.0],
+		])
+	}
+
+	pub fn rotation_x(angle:f64) -> Matrix4{
+		return Matrix4::rotation(&Vector3::new(1.0, 0.0, 0.0), angle);
+	}
+
+	pub fn rotation_y(angle:f64) -> Matrix4{
+		return Matrix4::rotation(&Vector3::new(0.0, 1.0, 0.0), angle);
+	}
+
+	pub fn rotation_z(angle:f64) -> Matrix4{
+		return Matrix4::rotation(&Vector3::new(0.0, 0.0, 1.0), angle);
+	}
+
+	pub fn transpose(&self) -> Matrix4{
+		let mut m = [[0.0; 4]; 4];
+		for i in 0..4{
+			for j in 0..4{
+				m[i][j] = self.m[j][i];
+			}
+		}
+		return Matrix4::new(m);
+	}
+
+	//Gauss-Jordan elimination with partial pivoting, None if the matrix is singular
+	pub fn inverse(&self) -> Option<Matrix4>{
+		let mut a = self.m;
+		let mut inv = Matrix4::identity().m;
+
+		for column in 0..4{
+			let mut pivot = column;
+			for row in column + 1..4{
+				if a[row][column].abs() > a[pivot][column].abs(){
+					pivot = row;
+				}
+			}
+
+			if a[pivot][column].abs() < 1e-300 {return None;}
+
+			a.swap(column, pivot);
+			inv.swap(column, pivot);
+
+			let scale = 1.0 / a[column][column];
+			for j in 0..4{
+				a[column][j] *= scale;
+				inv[column][j] *= scale;
+			}
+
+			for row in 0..4{
+				if row == column {continue;}
+				let factor = a[row][column];
+				if factor == 0.0 {continue;}
+				for j in 0..4{
+					a[row][j] -= factor * a[column][j];
+					inv[row][j] -= factor * inv[column][j];
+				}
+			}
+		}
+
+		return Some(Matrix4::new(inv));
+	}
+
+	pub fn transform_point(&self, p:&Vector3) -> Vector3{
+		let m = &self.m;
+		let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+		let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+		let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+		let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+
+		if w == 1.0 {return Vector3::new(x, y, z);}
+		return Vector3::new(x / w, y / w, z / w);
+	}
+
+	pub fn transform_vector(&self, v:&Vector3) -> Vector3{
+		let m = &self.m;
+		Vector3::new(
+			m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+			m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+			m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z)
+	}
+
+	//Multiplies by the transpose without building it, used to carry normals with the inverse matrix
+	pub fn transform_vector_transposed(&self, v:&Vector3) -> Vector3{
+		let m = &self.m;
+		Vector3::new(
+			m[0][0] * v.x + m[1][0] * v.y + m[2][0] * v.z,
+			m[0][1] * v.x + m[1][1] * v.y + m[2][1] * v.z,
+			m[0][2] * v.x + m[1][2] * v.y + m[2][2] * v.z)
+	}
+}
+
+impl Default for Matrix4{
+	fn default() -> Self{
+		Matrix4::identity()
+	}
+}
+
+impl ops::Mul<Matrix4> for Matrix4{
+	type Output = Matrix4;
+
+	fn mul(self, other:Matrix4) -> Matrix4{
+		let mut m = [[0.0; 4]; 4];
+		for i in 0..4{
+			for j in 0..4{
+				m[i][j] = self.m[i][0] * other.m[0][j] + self.m[i][1] * other.m[1][j] + self.m[i][2] * other.m[2][j] + self.m[i][3] * other.m[3][j];
+			}
+		}
+		return Matrix4::new(m);
+	}
+}
+
+//Object to world matrix with its inverse, kept together so rays and normals never need an inversion
+#[derive(Debug, Clone, Copy)]
+pub struct Transform{
+	pub matrix:Matrix4,
+	pub inverse:Matrix4,
+}
+
+impl Transform{
+	pub fn new(matrix:Matrix4) -> Transform{
+		let inverse = matrix.inverse().expect("Transform matrix is not invertible");
+		Transform{
+			matrix,
+			inverse,
+		}
+	}
+
+	pub fn identity() -> Transform{
+		Transform{
+			matrix:Matrix4::identity(),
+			inverse:Matrix4::identity(),
+		}
+	}
+
+	pub fn translation(t:&Vector3) -> Transform{
+		return Transform::new(Matrix4::translation(t));
+	}
+
+	pub fn scale(s:&Vector3) -> Transform{
+		return Transform::new(Matrix4::scale(s));
+	}
+
+	pub fn rotation(axis:&Vector3, angle:f64) -> Transform{
+		return Transform::new(Matrix4::rotation(axis, angle));
+	}
+
+	//Applies self first and then other
+	pub fn then(&self, other:&Transform) -> Transform{
+		Transform{
+			matrix:other.matrix * self.matrix,
+			inverse:self.inverse * other.inverse,
+		}
+	}
+
+	pub fn point_to_world(&self, p:&Vector3) -> Vector3{
+		return self.matrix.transform_point(p);
+	}
+
+	pub fn point_to_object(&self, p:&Vector3) -> Vector3{
+		return self.inverse.transform_point(p);
+	}
+
+	pub fn vector_to_world(&self, v:&Vector3) -> Vector3{
+		return self.matrix.transform_vector(v);
+	}
+
+	pub fn vector_to_object(&self, v:&Vector3) -> Vector3{
+		return self.inverse.transform_vector(v);
+	}
+
+	//Normals use the inverse transpose so they stay perpendicular under non uniform scale
+	pub fn normal_to_world(&self, n:&Vector3) -> Vector3{
+		return self.inverse.transform_vector_transposed(n).normalize();
+	}
+
+	pub fn normal_to_object(&self, n:&Vector3) -> Vector3{
+		return self.matrix.transform_vector_transposed(n).normalize();
+	}
+}
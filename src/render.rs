@@ -5,19 +5,40 @@ use image::{ImageBuffer, RgbImage, Rgb};
 //extern crate nalgebra as na;
 //use na::Vector3;
 use crate::vector3::Vector3;
-use rand::Rng;
 use crate::intersection::Ray;
+use crate::spectrum::Wavelengths;
+use crate::sampling;
 
 mod raytracing;
 pub mod raytracing_config;
+mod emitters;
 use raytracing_config::RaytracingConfig;
+use emitters::Emitters;
 
 use std::sync::{Arc, Mutex};
 use rayon::prelude::*;
 
 extern crate oidn;
 
-pub fn render(scene:&Scene, width:u32, height:u32, raytracing_config:RaytracingConfig) -> RgbImage{
+//Geometry edited since the last bvh build is picked up here, the bvh is rebuilt before tracing
+pub fn render(scene:&mut Scene, width:u32, height:u32, raytracing_config:RaytracingConfig) -> RgbImage{
+    scene.update_bvh();
+    let (mut final_image, normals, albedos) = trace_image(scene, width, height, raytracing_config);
+
+    if raytracing_config.denoise{
+        to_rgb(&normals).save("normal.png").unwrap();
+        to_rgb(&albedos).save("albedo.png").unwrap();
+
+        denoise(&mut final_image, normals, albedos, raytracing_config);
+    }
+    
+    return to_rgb(&final_image);
+}
+
+type LinearImage = ImageBuffer<Rgb<f32>, Vec<f32>>;
+
+//Color, normal and albedo of every pixel before denoising
+fn trace_image(scene:&Scene, width:u32, height:u32, raytracing_config:RaytracingConfig) -> (LinearImage, LinearImage, LinearImage){
     //ImageBuffer<Rgb<u8>, Vec<u8>>
     let arc_img = Arc::new(Mutex::new(ImageBuffer::<Rgb<f32>, Vec<f32>>::new(width, height)));
     let arc_normal = Arc::new(Mutex::new(ImageBuffer::<Rgb<f32>, Vec<f32>>::new(width, height)));
@@ -32,6 +53,9 @@ pub fn render(scene:&Scene, width:u32, height:u32, raytracing_config:RaytracingC
     let camera_height = (scene.main_camera.fov / 2.0 * std::f64::consts::PI / 180.0).tan();
     let camera_width = image_aspect_ratio * camera_height;
 
+    //Emissive objects light sampling picks points on
+    let emitters = Emitters::new(scene);
+
     //Camera vectors
     let origin = scene.main_camera.position;
     let forward = scene.main_camera.forward;
@@ -39,11 +63,11 @@ pub fn render(scene:&Scene, width:u32, height:u32, raytracing_config:RaytracingC
     let up = scene.main_camera.up;
 
     fn render_line(pixel_y:u32, height: u32, width: u32, origin: Vector3, forward: Vector3, right: Vector3, up: Vector3,
-        camera_width: f64, camera_height: f64, focus_distance: f64, focus_blur: f64, scene: &Scene,
+        camera_width: f64, camera_height: f64, focus_distance: f64, focus_blur: f64, scene: &Scene, emitters: &Emitters,
         raytracing_config:RaytracingConfig, img: &Arc<Mutex<ImageBuffer<Rgb<f32>, Vec<f32>>>>,
         normals: &Arc<Mutex<ImageBuffer<Rgb<f32>, Vec<f32>>>>, albedos: &Arc<Mutex<ImageBuffer<Rgb<f32>, Vec<f32>>>>){
         
-        let mut rng = rand::thread_rng();
+        let mut sampler = raytracing_config.sampler.create(raytracing_config.seed);
         for pixel_x in 0..width{
             let mut color:Vector3 = Vector3::new(0.0, 0.0, 0.0);
             let mut normal:Vector3 = Vector3::default();
@@ -56,15 +80,17 @@ pub fn render(scene:&Scene, width:u32, height:u32, raytracing_config:RaytracingC
             
             //for _i in 0..rays_per_pixel{ //fixed ray count per pixel
             while !did_converge(&mut prev_color, &color, &mut last_pixel_update, current_ray_count, raytracing_config.convergence_threshold){ //attention to detail algorithm
+                sampler.start_pixel_sample((pixel_x, pixel_y), current_ray_count);
                 let mut pixel_x = pixel_x as f64;
                 let mut pixel_y = pixel_y as f64;
                 
+                let (jitter_x, jitter_y) = sampler.get_2d();
                 if raytracing_config.rays_per_pixel == 1{
                     pixel_x +=  0.5;
                     pixel_y +=  0.5;
                 }else{
-                    pixel_x +=  rng.gen::<f64>();
-                    pixel_y +=  rng.gen::<f64>();
+                    pixel_x +=  jitter_x;
+                    pixel_y +=  jitter_y;
                 }
 
                 //Pixel coordinates in NDC space
@@ -84,15 +110,20 @@ pub fn render(scene:&Scene, width:u32, height:u32, raytracing_config:RaytracingC
 
                 //Focus blur
                 let focus_point = focus_distance * ray_direction + origin;
-                let mut offset = random_in_unit_disk();
-                offset = focus_blur * (offset.x * right + offset.y * up);
+                let (lens_x, lens_y) = sampler.get_2d();
+                let ((disk_x, disk_y), _) = sampling::uniform_disk(lens_x, lens_y);
+                let offset = focus_blur * (disk_x * right + disk_y * up);
                 let blur_origin = origin + offset;
                 ray_direction = (focus_point - blur_origin).normalize();
 
+                //Spectral samples pick their wavelengths here
+                let wavelengths = if raytracing_config.spectral {Some(Wavelengths::sample(sampler.get_1d()))} else {None};
+
                 //Create Ray
                 let ray = Ray{
                     origin: blur_origin,
                     direction: ray_direction,
+                    wavelengths,
                 };
 
                 //Cast Ray
@@ -100,7 +131,11 @@ pub fn render(scene:&Scene, width:u32, height:u32, raytracing_config:RaytracingC
 
                 let mut new_normal:Vector3 = Vector3::default();
                 let mut new_albedo:Vector3 = Vector3::default();
-                color += raytracing::cast_ray_with_normal_albedo(&scene, &ray, raytracing_config.ray_bounce_max_depth, &mut new_normal, &mut new_albedo);
+                let radiance = raytracing::cast_ray_with_normal_albedo(&scene, emitters, &ray, &raytracing_config, &mut new_normal, &mut new_albedo, &mut *sampler);
+                color += match wavelengths{
+                    Some(w) => w.to_rgb(&radiance),
+                    None => radiance,
+                };
                 normal += new_normal;
                 albedo += new_albedo;
 
@@ -112,6 +147,10 @@ pub fn render(scene:&Scene, width:u32, height:u32, raytracing_config:RaytracingC
             normal = 1.0 / current_ray_count as f64 * normal;
             albedo = 1.0 / current_ray_count as f64 * albedo;
 
+            //Spectral samples can average to a little below 0 for saturated colors, the power would
+            //make that NaN. Clamping the pixel mean instead of each sample keeps the estimate unbiased.
+            color = Vector3::new(color.x.max(0.0), color.y.max(0.0), color.z.max(0.0));
+
             //Gamma correction and clamp
             color = raytracing_config.exposure * color;
             color.x = (f64::powf(color.x, raytracing_config.gamma)).clamp(0.0, 1.0);
@@ -140,28 +179,18 @@ pub fn render(scene:&Scene, width:u32, height:u32, raytracing_config:RaytracingC
     if raytracing_config.parallel{
         //Render in parallel with rayon
         (0..height).into_par_iter().for_each( | line | render_line(line, height, width, origin, forward, right, up, camera_width, camera_height,
-            focus_distance, focus_blur, scene, raytracing_config, &arc_img, &arc_normal, &arc_albedo));
+            focus_distance, focus_blur, scene, &emitters, raytracing_config, &arc_img, &arc_normal, &arc_albedo));
     }else{
         for line in 0..height{
             render_line(line, height, width, origin, forward, right, up, camera_width, camera_height,
-                focus_distance, focus_blur, scene, raytracing_config, &arc_img, &arc_normal, &arc_albedo);
+                focus_distance, focus_blur, scene, &emitters, raytracing_config, &arc_img, &arc_normal, &arc_albedo);
         }
     }
 
-    let img = &*arc_img.lock().unwrap();
-    let mut final_image = img.clone();
-
-    if raytracing_config.denoise{
-        let normals = (&*arc_normal.lock().unwrap()).clone();
-        let albedos = (&*arc_albedo.lock().unwrap()).clone();
-
-        to_rgb(&normals).save("normal.png").unwrap();
-        to_rgb(&albedos).save("albedo.png").unwrap();
-
-        denoise(&mut final_image, normals, albedos, raytracing_config);
-    }
-    
-    return to_rgb(&final_image);
+    let img = arc_img.lock().unwrap().clone();
+    let normals = arc_normal.lock().unwrap().clone();
+    let albedos = arc_albedo.lock().unwrap().clone();
+    return (img, normals, albedos);
 }
 
 fn denoise(img: &mut ImageBuffer::<Rgb<f32>, Vec<f32>>, normal: ImageBuffer::<Rgb<f32>, Vec<f32>>, albedo: ImageBuffer::<Rgb<f32>, Vec<f32>>, raytracing_config: RaytracingConfig){
@@ -271,13 +300,55 @@ fn did_converge(last_color:&mut Vector3, color:& Vector3, last_update:&mut u32,
     }
 }
 
-fn random_in_unit_disk() -> Vector3{
-    let mut rng = rand::thread_rng();
-    loop{
-        let x = rng.gen_range(-1.0 .. 1.0);
-        let y = rng.gen_range(-1.0 .. 1.0);
-        if (x*x + y*y) <= 1.0{
-            return Vector3::new(x, y, 0.0);
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::sampler::SamplerKind;
+
+    fn config(parallel:bool, seed:u64, sampler:SamplerKind) -> RaytracingConfig{
+        return RaytracingConfig{
+            exposure:1.0, gamma:2.2, rays_per_pixel:0, ray_bounce_max_depth:5, convergence_threshold:0.2,
+            diffuse_max_depth:3, glossy_max_depth:4, transmission_max_depth:5, portal_max_depth:5, volume_max_depth:128, russian_roulette_depth:3,
+            parallel, denoise:false, denoise_with_normals:false, spectral:false, sampler, seed};
+    }
+
+    //Small scene with an area light so light sampling and russian roulette draw numbers too
+    fn small_scene() -> Scene{
+        let mut scene = scene::load_scene_spheres(16);
+        scene.create_sphere(Vector3::new(0.0, 30.0, 0.0), 5.0, &String::from("emission_white"));
+        scene.build_bvh();
+        return scene;
+    }
+
+    fn trace_raw(scene:&Scene, config:RaytracingConfig) -> Vec<u32>{
+        let (img, normals, albedos) = trace_image(scene, 32, 24, config);
+        return img.as_raw().iter().chain(normals.as_raw().iter()).chain(albedos.as_raw().iter()).map(|value| value.to_bits()).collect();
+    }
+
+    #[test]
+    fn parallel_render_matches_serial_render(){
+        let scene = small_scene();
+        for sampler in [SamplerKind::Independent, SamplerKind::Stratified{strata:2}, SamplerKind::Halton, SamplerKind::Sobol, SamplerKind::BlueNoise]{
+            let serial = trace_raw(&scene, config(false, 3, sampler));
+            assert!(serial == trace_raw(&scene, config(true, 3, sampler)), "{:?}", sampler);
         }
     }
+
+    #[test]
+    fn seed_changes_the_image(){
+        let scene = small_scene();
+        assert!(trace_raw(&scene, config(true, 3, SamplerKind::Independent)) != trace_raw(&scene, config(true, 4, SamplerKind::Independent)));
+    }
+
+    //Light of a single wavelength falls outside the RGB gamut, the pixels must still be valid
+    #[test]
+    fn spectral_render_of_saturated_light_has_no_nan(){
+        let mut scene = small_scene();
+        scene.add_material(String::from("emission_white"), crate::scene::materials::Emission::create(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 40.0)));
+        scene.lights.clear();
+        let mut spectral = config(true, 3, SamplerKind::Independent);
+        spectral.spectral = true;
+        let (img, _, _) = trace_image(&scene, 32, 24, spectral);
+        assert!(img.as_raw().iter().all(|value| value.is_finite()));
+    }
 }
\ No newline at end of file
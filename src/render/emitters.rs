@@ -0,0 +1,53 @@
+use crate::scene::Scene;
+use crate::scene::bsdf::Bsdf;
+use crate::scene::objects::Object;
+use crate::intersection::HitData;
+use crate::sampler::Sampler;
+use crate::vector3::Vector3;
+
+//Objects with an emissive material and a bounded shape, sampled as area lights. Each light sample
+//picks one of them uniformly and then a point on it.
+pub struct Emitters{
+	objects:Vec<usize>,
+}
+
+impl Emitters{
+	pub fn new(scene:&Scene) -> Emitters{
+		let objects = scene.objects().iter().enumerate()
+			.filter(|(_, object)| Emitters::is_sampled(scene, object))
+			.map(|(i, _)| i)
+			.collect();
+
+		return Emitters{
+			objects,
+		};
+	}
+
+	fn is_sampled(scene:&Scene, object:&Object) -> bool{
+		return scene.material(object.material).is_emitter() && object.shape.has_area();
+	}
+
+	pub fn is_empty(&self) -> bool{
+		return self.objects.is_empty();
+	}
+
+	//Emitter and point on it for a light sample from reference
+	pub fn sample<'a>(&self, scene:&'a Scene, reference:&Vector3, sampler:&mut dyn Sampler) -> Option<(&'a Object, Vector3)>{
+		if self.objects.is_empty(){
+			return None;
+		}
+
+		let choice = ((sampler.get_1d() * self.objects.len() as f64) as usize).min(self.objects.len() - 1);
+		let object = &scene.objects()[self.objects[choice]];
+		let point = object.shape.sample_from(reference, sampler.get_2d())?;
+		return Some((object, point));
+	}
+
+	//Solid angle density of sample reaching the hit from reference, 0 for objects that are not sampled
+	pub fn pdf(&self, scene:&Scene, reference:&Vector3, hit_data:&HitData) -> f64{
+		if !Emitters::is_sampled(scene, hit_data.object){
+			return 0.0;
+		}
+		return hit_data.object.shape.pdf_from(reference, &hit_data.point, &hit_data.geometric_norm) / self.objects.len() as f64;
+	}
+}
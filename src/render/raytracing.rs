@@ -1,11 +1,16 @@
 #![allow(dead_code,unused_variables)]
 use crate::scene;
 use scene::Scene;
-use scene::materials::Material;
+use scene::bsdf::{Bsdf, BsdfLobe};
 use crate::intersection;
 use intersection::Ray;
 use intersection::Hit;
 use intersection::HitData;
+use crate::spectrum;
+use crate::sampler::Sampler;
+use crate::sampling;
+use super::raytracing_config::RaytracingConfig;
+use super::emitters::Emitters;
 
 //extern crate nalgebra as na;
 //use na::Vector3;
@@ -13,72 +18,164 @@ use crate::vector3::Vector3;
 
 static DISPLACEMENT_DISTANCE:f64 = 0.0000001;
 
-pub fn cast_ray_with_normal_albedo(scene:&Scene, ray:&Ray, depth:u8, normal:&mut Vector3, albedo:&mut Vector3) -> Vector3{
-	let intersection = intersection::raycast(scene, ray);
-
-	match intersection{
-		Hit::Nothing => {
-			//Skybox
-			*normal = ray.direction;
-			*albedo = skybox(scene, ray);
-			return *albedo;
-		},
-		Hit::Something(ref hit_data) => {
+//Follows one path from the camera ray, adding the light found at every hit times the throughput of the
+//path up to it. The path ends when it leaves the scene, is absorbed, goes past a depth limit of the config
+//or loses the russian roulette.
+//
+//Emitters are found both by light sampling at every hit and by the bounces themselves, multiple importance
+//sampling weights the two so each emitter is only counted once.
+pub fn cast_ray_with_normal_albedo(scene:&Scene, emitters:&Emitters, ray:&Ray, raytracing_config:&RaytracingConfig, normal:&mut Vector3, albedo:&mut Vector3, sampler:&mut dyn Sampler) -> Vector3{
+	let mut radiance = Vector3::new(0.0, 0.0, 0.0);
+	let mut throughput = Vector3::new(1.0, 1.0, 1.0);
+	let mut depths = PathDepths::default();
+	//Point and bsdf pdf of the last bounce, when light sampling could have taken the same direction
+	let mut last_bounce:Option<(Vector3, f64)> = None;
+	let mut ray = Ray{
+		origin: ray.origin,
+		direction: ray.direction,
+		wavelengths: ray.wavelengths,
+	};
+
+	for bounce in 0..{
+		let mut hit_data = match intersection::raycast(scene, &ray){
+			Hit::Nothing => {
+				//Skybox
+				let sky = skybox(scene, &ray);
+				if bounce == 0{
+					*normal = ray.direction;
+					*albedo = sky;
+				}
+				return radiance + throughput.mult(&sky);
+			},
+			Hit::Something(hit_data) => hit_data,
+		};
+
+		let material = scene.material(hit_data.object.material);
+		material.perturb(&mut hit_data);
+		if bounce == 0{
 			*normal = hit_data.norm;
-			*albedo = hit_data.object.material.attenuation();
-			return //color_mult(&scene.ambient_light, &hit_data.object.material.color)
-				//hit_data.object.material.emission
-				compute_direct_illumination(scene, &ray.direction, &hit_data) +
-				compute_indirect_illumination(scene, ray, &hit_data, depth);
-		},
+			*albedo = material.albedo(&hit_data);
+		}
+
+		//The segment that ended here may have crossed an absorbing object
+		throughput = throughput.mult(&material.transmittance(&hit_data, &ray.wavelengths));
+		let mut emitted = material.emitted(&hit_data, &ray.wavelengths);
+		if let Some((last_point, bsdf_pdf)) = last_bounce{
+			let light_pdf = emitters.pdf(scene, &last_point, &hit_data);
+			if light_pdf > 0.0{
+				emitted = sampling::power_heuristic(bsdf_pdf, light_pdf) * emitted;
+			}
+		}
+
+		let light = compute_direct_illumination(scene, &ray, &hit_data) + sample_emitters(scene, emitters, &ray, &hit_data, sampler) + emitted;
+		radiance += throughput.mult(&light);
+
+		let mut wavelengths = ray.wavelengths;
+		let sample = match material.sample(&ray.direction, &hit_data, &mut wavelengths, sampler){
+			Some(sample) => sample,
+			None => return radiance,
+		};
+		if !depths.add(sample.lobe, raytracing_config){
+			return radiance;
+		}
+
+		let mut weight = sample.weight;
+		if !sample.delta && !material.flags(&hit_data).transmissive{
+			weight = shadow_terminator(&hit_data, &sample.direction) * weight;
+		}
+		throughput = throughput.mult(&weight);
+
+		//Dim paths end at random, the survivors carry the light of the ended ones
+		if bounce >= raytracing_config.russian_roulette_depth as u32{
+			let survival = throughput.x.max(throughput.y).max(throughput.z).min(1.0);
+			if sampler.get_1d() >= survival{
+				return radiance;
+			}
+			throughput = (1.0 / survival) * throughput;
+		}
+
+		//Bounces out of a volume start away from the hit, light sampling didn't look from there
+		last_bounce = if sample.pdf > 0.0 && sample.origin.is_none() {Some((hit_data.point, sample.pdf))} else {None};
+
+		let mut origin = sample.origin.unwrap_or(hit_data.point);
+
+		//Computing displacement point to prevent point float errors
+		if hit_data.geometric_norm.dot(&sample.direction) >= 0.0{
+			origin +=  DISPLACEMENT_DISTANCE * hit_data.geometric_norm;
+		}else{
+			origin += - DISPLACEMENT_DISTANCE * hit_data.geometric_norm;
+		}
+
+		ray = Ray{
+			origin,
+			direction: sample.direction,
+			wavelengths,
+		};
 	}
+
+	return radiance;
+}
+
+//Bounces a path took so far, per kind of lobe
+#[derive(Default)]
+struct PathDepths{
+	surface:u32,
+	diffuse:u32,
+	glossy:u32,
+	transmission:u32,
+	portal:u32,
+	volume:u32,
 }
 
-pub fn cast_ray(scene:&Scene, ray:&Ray, depth:u8) -> Vector3{
-	let intersection = intersection::raycast(scene, ray);
-
-	match intersection{
-		Hit::Nothing => {
-			return skybox(scene, ray);
-		},
-		Hit::Something(ref hit_data) => {
-			return //color_mult(&scene.ambient_light, &hit_data.object.material.color)
-				//hit_data.object.material.emission
-				compute_direct_illumination(scene, &ray.direction, &hit_data) +
-				compute_indirect_illumination(scene, ray, &hit_data, depth);
-		},
+impl PathDepths{
+	//Counts a bounce, false once it takes the path past a limit. Volume bounces only have their own limit
+	//so a random walk doesn't use up the surface bounces.
+	fn add(&mut self, lobe:BsdfLobe, raytracing_config:&RaytracingConfig) -> bool{
+		let (depth, max_depth) = match lobe{
+			BsdfLobe::Diffuse => (&mut self.diffuse, raytracing_config.diffuse_max_depth),
+			BsdfLobe::Glossy => (&mut self.glossy, raytracing_config.glossy_max_depth),
+			BsdfLobe::Transmission => (&mut self.transmission, raytracing_config.transmission_max_depth),
+			BsdfLobe::Portal => (&mut self.portal, raytracing_config.portal_max_depth),
+			BsdfLobe::Volume => {
+				self.volume += 1;
+				return self.volume <= raytracing_config.volume_max_depth as u32;
+			},
+		};
+
+		*depth += 1;
+		self.surface += 1;
+		return *depth <= max_depth as u32 && self.surface <= raytracing_config.ray_bounce_max_depth as u32;
 	}
 }
 
 fn skybox(scene:&Scene, ray:&Ray) -> Vector3{
 	let t = ray.direction.y.abs(); //0.5 * (ray.direction.y + 1.0);
-	return t * scene.gradient_light_1 + (1.0 - t) * scene.gradient_light_2;
+	return spectrum::carried(&(t * scene.gradient_light_1 + (1.0 - t) * scene.gradient_light_2), &ray.wavelengths);
 	//return Vector3::new(0.0,0.0,0.0);
 }
 
-fn compute_direct_illumination(scene:&Scene, direction:&Vector3, hit_data:&HitData) -> Vector3{
+fn compute_direct_illumination(scene:&Scene, in_ray:&Ray, hit_data:&HitData) -> Vector3{
+	let direction = &in_ray.direction;
+	let wavelengths = &in_ray.wavelengths;
 	let mut color = Vector3::new(0.0, 0.0, 0.0);
+	let material = scene.material(hit_data.object.material);
 
-	match hit_data.object.material{
-		Material::Glass(_) | Material::Metal(_) | Material::Portal(_) => {
-			return color;
-		}
-		Material::Diffuse(_) | Material::Emission(_) => {/*continue*/}
+	//Mirrors and glass can't catch a point light, their highlight comes from reflections
+	let flags = material.flags(hit_data);
+	if flags.delta{
+		return color;
 	}
 
 	let effective_norm:Vector3;
-	let vision_direction = -1.0 * direction;
 
 	if hit_data.inside{
 		//If they are in the same direction reflection/surface norm must be inverted.
 		//Inside object, effective norm is flipped of surface norm
-		effective_norm = -hit_data.norm;
+		effective_norm = -hit_data.geometric_norm;
 	}else{
-		effective_norm = hit_data.norm;
+		effective_norm = hit_data.geometric_norm;
 	}
 
-	let displacement_point = hit_data.point + DISPLACEMENT_DISTANCE * effective_norm;
-
 	//Direct light
 	for light in &scene.lights{
 		//Compute distance and direction to light
@@ -87,79 +184,151 @@ fn compute_direct_illumination(scene:&Scene, direction:&Vector3, hit_data:&HitDa
 		//normalize
 		light_dir = 1.0/light_distance * light_dir;
 
-		//Cos between norm and light
-		let cos = hit_data.norm.dot(&light_dir);
+		//Light can reach transmissive surfaces from the other side, the shadow ray starts on the side of the light
+		let side = if flags.transmissive && effective_norm.dot(&light_dir) < 0.0 {-1.0} else {1.0};
+		let displacement_point = hit_data.point + (side * DISPLACEMENT_DISTANCE) * effective_norm;
 
 		let ray = Ray{
 			origin: displacement_point,
 			direction: light_dir,
+			wavelengths: *wavelengths,
 		};
 
-		let intersection = intersection::raycast(scene, &ray);
-
-		fn compute_color(cos:f64, material:&Material, light_color:Vector3, light_dir: &Vector3, effective_norm:&Vector3, direction: &Vector3) -> Vector3{
-			return cos.abs() * light_color.mult(&material.attenuation()) + light_color.mult(&material.specular(light_dir, effective_norm, direction));
-		}
-
-		match intersection{
-			Hit::Nothing => {
-				//compute color probably needs -direction instead
-				color += compute_color(cos, &hit_data.object.material, light.get_color_attenuated(light_distance), &light_dir, &effective_norm, &direction);
-			},
-			Hit::Something(ref light_hit_data) => {
-				if light_hit_data.distance >= light_distance{
-					color += compute_color(cos, &hit_data.object.material, light.get_color_attenuated(light_distance), &light_dir, &effective_norm, &direction);
-				}
-			},
+		if !intersection::occluded(scene, &ray, light_distance){
+			let light_color = spectrum::carried(&light.get_color_attenuated(light_distance), wavelengths);
+			let mut value = material.eval(direction, &light_dir, hit_data, wavelengths);
+			if !flags.transmissive{
+				value = shadow_terminator(hit_data, &light_dir) * value;
+			}
+			color += light_color.mult(&value);
 		}
 	}
 
-	//color = color.mult(&hit_data.object.material.attenuation());
 	return color;
 }
 
-fn compute_indirect_illumination(scene:&Scene, in_ray:&Ray, hit_data:&HitData, depth:u8) -> Vector3{
-	let mut color = Vector3::new(0.0, 0.0, 0.0);
+//Light from a point sampled on the emitters, weighted against the bsdf sampling that may also find it
+fn sample_emitters(scene:&Scene, emitters:&Emitters, in_ray:&Ray, hit_data:&HitData, sampler:&mut dyn Sampler) -> Vector3{
+	let no_light = Vector3::new(0.0, 0.0, 0.0);
+	let material = scene.material(hit_data.object.material);
+	let flags = material.flags(hit_data);
+	if flags.delta || emitters.is_empty(){
+		return no_light;
+	}
 
-	if depth > 0 {
-		let mut out_ray = Ray{
-			origin: Vector3::new(0.0, 0.0, 0.0),
-			direction: Vector3::new(0.0, 0.0, 0.0),
-		};
+	let (object, point) = match emitters.sample(scene, &hit_data.point, sampler){
+		Some(sample) => sample,
+		None => return no_light,
+	};
 
-		out_ray.origin = hit_data.point;
-		
-		if hit_data.object.material.scatter(&in_ray.direction, &hit_data, &mut out_ray){
-			//Computing displacement point to prevent point float errors
-			if hit_data.norm.dot(&out_ray.direction) >= 0.0{
-				out_ray.origin +=  DISPLACEMENT_DISTANCE * hit_data.norm;
-			}else{
-				out_ray.origin += - DISPLACEMENT_DISTANCE * hit_data.norm;
-			}
+	let to_light = point - hit_data.point;
+	let light_distance = to_light.norm();
+	if light_distance <= 0.0{
+		return no_light;
+	}
+	let light_dir = 1.0 / light_distance * to_light;
 
-			//The math is with effective_norm instead of norm, however, we do a cos.abs() anyway
-			//let cos = hit_data.norm.dot(&in_ray.direction);
-			color =  /* cos.abs() * */  cast_ray(scene, &out_ray, depth - 1);
+	//Bsdf sampling can't reach the direction, the bounce gets the whole emitter instead
+	let bsdf_pdf = material.pdf(&in_ray.direction, &light_dir, hit_data, &in_ray.wavelengths);
+	if bsdf_pdf <= 0.0{
+		return no_light;
+	}
 
-			if let Material::Diffuse(m) = hit_data.object.material{
+	//The shadow ray leaves on the side of the light, eval is 0 for opaque materials lit from behind
+	let effective_norm = if hit_data.inside {-hit_data.geometric_norm} else {hit_data.geometric_norm};
+	let side = if effective_norm.dot(&light_dir) < 0.0 {-1.0} else {1.0};
+	let ray = Ray{
+		origin: hit_data.point + (side * DISPLACEMENT_DISTANCE) * effective_norm,
+		direction: light_dir,
+		wavelengths: in_ray.wavelengths,
+	};
 
-				let effective_norm:Vector3;
-				if hit_data.inside{
-					effective_norm = -1.0 * hit_data.norm;
-				}else{
-					effective_norm = hit_data.norm;
-				}
+	//Only the sampled point counts, other objects and the far side of the emitter itself block it
+	let light_hit = match intersection::raycast(scene, &ray){
+		Hit::Something(light_hit) if std::ptr::eq(light_hit.object, object) && light_hit.distance > (1.0 - 1e-4) * light_distance => light_hit,
+		_ => return no_light,
+	};
 
-				color += color.mult(&m.specular(&out_ray.direction, &effective_norm, &in_ray.direction));
-			}
-		}
+	let light_pdf = emitters.pdf(scene, &hit_data.point, &light_hit);
+	if light_pdf <= 0.0{
+		return no_light;
+	}
 
-		color = color.mult(&hit_data.object.material.attenuation());
+	let emitted = scene.material(object.material).emitted(&light_hit, &in_ray.wavelengths);
+	let mut value = material.eval(&in_ray.direction, &light_dir, hit_data, &in_ray.wavelengths);
+	if !flags.transmissive{
+		value = shadow_terminator(hit_data, &light_dir) * value;
 	}
+	return (sampling::power_heuristic(light_pdf, bsdf_pdf) / light_pdf) * emitted.mult(&value);
+}
+
+//Shadowing of a shading normal that differs from the surface (Chiang et al. 2019), smooths the
+//terminator of bump maps and coarse smooth meshes and keeps light under the surface out
+fn shadow_terminator(hit_data:&HitData, direction:&Vector3) -> f64{
+	let side = if hit_data.inside {-1.0} else {1.0};
+	let cos_shading = side * hit_data.norm.dot(direction);
+	let cos_geometric = side * hit_data.geometric_norm.dot(direction);
 
-	if let Material::Emission(m) = hit_data.object.material{
-		color += m.emission;
+	if cos_shading <= 0.0{
+		return 1.0;
+	}
+	if cos_geometric <= 0.0{
+		return 0.0;
 	}
 
-	return color;
-}
\ No newline at end of file
+	let g = (cos_geometric / (cos_shading * hit_data.norm.dot(&hit_data.geometric_norm))).min(1.0);
+	return -g * g * g + g * g + g;
+}
+
+#[cfg(test)]
+mod tests{
+	use super::*;
+	use crate::scene::{Camera, lights::PointLight};
+	use crate::scene::materials::{Material, Diffuse};
+	use std::f64::consts::PI;
+
+	//Legacy diffuse under a point light of PI times the old color shades as the old compute_color did
+	#[test]
+	fn legacy_diffuse_keeps_the_old_point_light_shading(){
+		let mut material = Diffuse::create_legacy(Vector3::new(0.8, 0.4, 0.2));
+		if let Material::Diffuse(ref mut m) = material{
+			m.specular_color = Vector3::new(0.5, 0.6, 0.7);
+			m.shininess = 2.0;
+			m.specular_exp = 10.0;
+		}
+		let camera = Camera::new(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), 60.0);
+		let mut scene = Scene::new(camera);
+		scene.add_material(String::from("legacy"), material);
+		scene.create_sphere(Vector3::new(0.0, 0.0, 0.0), 1.0, &String::from("legacy"));
+		let light_position = Vector3::new(1.0, 2.0, 3.0);
+		let light_color = Vector3::new(2.0, 3.0, 4.0);
+		scene.lights.push(PointLight::create(light_position, PI * light_color));
+		scene.build_bvh();
+
+		//Near the mirror direction of the light so the highlight shows
+		let ray = Ray{
+			origin:Vector3::new(0.2, 0.3, 5.0),
+			direction:(Vector3::new(0.3, 0.4, 0.87).normalize() - Vector3::new(0.2, 0.3, 5.0)).normalize(),
+			wavelengths:None,
+		};
+		let hit_data = match intersection::raycast(&scene, &ray){
+			Hit::Something(hit_data) => hit_data,
+			Hit::Nothing => panic!("the ray misses the sphere"),
+		};
+		let color = compute_direct_illumination(&scene, &ray, &hit_data);
+
+		//compute_color of the baseline with the old light color
+		let material = scene.material(hit_data.object.material);
+		let mut light_dir = light_position - hit_data.point;
+		let light_distance = light_dir.norm();
+		light_dir = 1.0 / light_distance * light_dir;
+		let old_color = PointLight::create(light_position, light_color).get_color_attenuated(light_distance);
+		let cos = hit_data.norm.dot(&light_dir);
+		let expected = cos.abs() * old_color.mult(&material.attenuation(&hit_data)) + old_color.mult(&material.specular(&light_dir, &hit_data.norm, &ray.direction));
+
+		assert!(material.specular(&light_dir, &hit_data.norm, &ray.direction).norm() > 0.01);
+		for i in 0..3{
+			assert!((color.component(i) - expected.component(i)).abs() < 1e-9, "{} {}", color.component(i), expected.component(i));
+		}
+	}
+}
@@ -1,4 +1,6 @@
 
+use crate::sampler::SamplerKind;
+
 #[derive(Copy, Clone)]
 pub struct RaytracingConfig{
 	//Color correction
@@ -10,10 +12,30 @@ pub struct RaytracingConfig{
 	pub ray_bounce_max_depth:u8,
 	pub convergence_threshold:f64,
 
+	//Bounces of each kind a path may take. A path ends at whichever limit it reaches first, its own
+	//kind's or ray_bounce_max_depth over all surface bounces together, so only values up to
+	//ray_bounce_max_depth make a difference. Volume bounces, the steps of a subsurface random walk,
+	//only count against their own.
+	pub diffuse_max_depth:u8,
+	pub glossy_max_depth:u8,
+	pub transmission_max_depth:u8,
+	pub portal_max_depth:u8,
+	pub volume_max_depth:u8,
+	//Bounces before russian roulette may end a path, the chance to go on follows the path throughput
+	pub russian_roulette_depth:u8,
+
 	//Parallelization properties
 	pub parallel:bool,
 
 	//Denoise
 	pub denoise:bool,
 	pub denoise_with_normals:bool,
+
+	//Trace wavelengths instead of RGB, needed for dispersion
+	pub spectral:bool,
+
+	//Source of the random numbers of camera, light and bsdf sampling
+	pub sampler:SamplerKind,
+	//Same seed, scene and config render the same image, in parallel or not
+	pub seed:u64,
 }
\ No newline at end of file
@@ -0,0 +1,493 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use std::sync::OnceLock;
+
+//Source of every uniform number a path consumes, camera, light and bsdf sampling alike. Each pixel
+//sample starts again at the first dimension, so the n-th number of a path lines up with the n-th
+//number of the other samples of the pixel and low discrepancy sequences can stratify them. The numbers
+//only depend on the seed, pixel, sample index and dimension, never on which thread renders the pixel.
+pub trait Sampler{
+	//Moves to sample index of pixel and back to the first dimension
+	fn start_pixel_sample(&mut self, pixel:(u32, u32), index:u32);
+
+	fn get_1d(&mut self) -> f64;
+
+	fn get_2d(&mut self) -> (f64, f64);
+}
+
+//Sampler picked in RaytracingConfig, create makes one per rendered line
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SamplerKind{
+	//White noise
+	Independent,
+	//Jittered strata shuffled per dimension, strata cells per axis of a 2d dimension
+	Stratified{strata:u32},
+	//Halton sequence with random digit scrambling per pixel
+	Halton,
+	//Sobol sequence with hashed Owen scrambling per pixel, see Burley "Practical Hash-based Owen Scrambling"
+	Sobol,
+	//One Sobol sequence for the whole image shifted per pixel by a blue noise mask, the error of nearby
+	//pixels is decorrelated and reads as fine grain, see Georgiev and Fajardo "Blue-noise Dithered Sampling"
+	BlueNoise,
+}
+
+impl SamplerKind{
+	pub fn create(&self, seed:u64) -> Box<dyn Sampler>{
+		match *self{
+			SamplerKind::Independent => Box::new(IndependentSampler{seed, rng:StdRng::seed_from_u64(seed)}),
+			SamplerKind::Stratified{strata} => Box::new(StratifiedSampler{strata:strata.max(1), seed, rng:StdRng::seed_from_u64(seed), pixel_seed:0, index:0, dimension:0}),
+			SamplerKind::Halton => Box::new(HaltonSampler{seed, pixel_seed:0, index:0, dimension:0}),
+			SamplerKind::Sobol => Box::new(SobolSampler{seed, pixel_seed:0, index:0, dimension:0}),
+			SamplerKind::BlueNoise => Box::new(BlueNoiseSampler{seed, pixel:(0, 0), index:0, dimension:0}),
+		}
+	}
+}
+
+//Largest f64 below 1
+const ONE_MINUS_EPSILON:f64 = 1.0 - f64::EPSILON / 2.0;
+
+//Every pixel sample gets its own generator
+pub struct IndependentSampler{
+	seed:u64,
+	rng:StdRng,
+}
+
+impl Sampler for IndependentSampler{
+	fn start_pixel_sample(&mut self, pixel:(u32, u32), index:u32){
+		self.rng = StdRng::seed_from_u64(hash(pixel_seed(self.seed, pixel), index as u64));
+	}
+
+	fn get_1d(&mut self) -> f64{
+		return self.rng.gen::<f64>();
+	}
+
+	fn get_2d(&mut self) -> (f64, f64){
+		return (self.rng.gen::<f64>(), self.rng.gen::<f64>());
+	}
+}
+
+//The strata of a dimension are visited in a shuffled order, a new one every strata * strata samples,
+//so the count of an adaptive render doesn't need to be known up front
+pub struct StratifiedSampler{
+	strata:u32,
+	seed:u64,
+	rng:StdRng,
+	pixel_seed:u64,
+	index:u32,
+	dimension:u32,
+}
+
+impl StratifiedSampler{
+	fn stratum(&mut self) -> u32{
+		let count = self.strata * self.strata;
+		let round = (self.index / count) as u64;
+		let seed = hash(self.pixel_seed, ((self.dimension as u64) << 32) | round);
+		self.dimension += 1;
+		return permutation_element(self.index % count, count, seed as u32);
+	}
+}
+
+impl Sampler for StratifiedSampler{
+	fn start_pixel_sample(&mut self, pixel:(u32, u32), index:u32){
+		self.pixel_seed = pixel_seed(self.seed, pixel);
+		self.rng = StdRng::seed_from_u64(hash(self.pixel_seed, index as u64));
+		self.index = index;
+		self.dimension = 0;
+	}
+
+	fn get_1d(&mut self) -> f64{
+		let count = (self.strata * self.strata) as f64;
+		let stratum = self.stratum() as f64;
+		return ((stratum + self.rng.gen::<f64>()) / count).min(ONE_MINUS_EPSILON);
+	}
+
+	fn get_2d(&mut self) -> (f64, f64){
+		let stratum = self.stratum();
+		let strata = self.strata as f64;
+		let x = (stratum % self.strata) as f64;
+		let y = (stratum / self.strata) as f64;
+		return (((x + self.rng.gen::<f64>()) / strata).min(ONE_MINUS_EPSILON), ((y + self.rng.gen::<f64>()) / strata).min(ONE_MINUS_EPSILON));
+	}
+}
+
+//Bases of the Halton dimensions, later dimensions fall back to hashed white noise
+const PRIMES:[u32; 64] = [
+	2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+	137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311,
+];
+
+pub struct HaltonSampler{
+	seed:u64,
+	pixel_seed:u64,
+	index:u32,
+	dimension:u32,
+}
+
+impl Sampler for HaltonSampler{
+	fn start_pixel_sample(&mut self, pixel:(u32, u32), index:u32){
+		self.pixel_seed = pixel_seed(self.seed, pixel);
+		self.index = index;
+		self.dimension = 0;
+	}
+
+	fn get_1d(&mut self) -> f64{
+		let dimension = self.dimension as usize;
+		let seed = hash(self.pixel_seed, dimension as u64);
+		self.dimension += 1;
+
+		if dimension >= PRIMES.len(){
+			return to_unit(hash(seed, self.index as u64));
+		}
+		return scrambled_radical_inverse(PRIMES[dimension], self.index as u64, seed);
+	}
+
+	fn get_2d(&mut self) -> (f64, f64){
+		return (self.get_1d(), self.get_1d());
+	}
+}
+
+//Digits of index in base mirrored around the point, every digit position goes through its own permutation
+fn scrambled_radical_inverse(base:u32, mut index:u64, seed:u64) -> f64{
+	let inv_base = 1.0 / base as f64;
+	let mut inv_base_m = 1.0;
+	let mut value = 0.0;
+	let mut digit_index = 0;
+
+	//Runs past the digits of index, the permuted zeros still move the point
+	while inv_base_m > 1.0 / 4294967296.0{
+		let digit = (index % base as u64) as u32;
+		let permuted = permutation_element(digit, base, mix_bits(seed ^ digit_index) as u32);
+		inv_base_m *= inv_base;
+		value += permuted as f64 * inv_base_m;
+		index /= base as u64;
+		digit_index += 1;
+	}
+	return value.min(ONE_MINUS_EPSILON);
+}
+
+//Each dimension shuffles the sample index on its own so the 2d Sobol points can pad any number of dimensions
+pub struct SobolSampler{
+	seed:u64,
+	pixel_seed:u64,
+	index:u32,
+	dimension:u32,
+}
+
+impl Sampler for SobolSampler{
+	fn start_pixel_sample(&mut self, pixel:(u32, u32), index:u32){
+		self.pixel_seed = pixel_seed(self.seed, pixel);
+		self.index = index;
+		self.dimension = 0;
+	}
+
+	fn get_1d(&mut self) -> f64{
+		let seed = hash(self.pixel_seed, self.dimension as u64);
+		self.dimension += 1;
+		return owen_sobol(self.index, seed).0;
+	}
+
+	fn get_2d(&mut self) -> (f64, f64){
+		let seed = hash(self.pixel_seed, self.dimension as u64);
+		self.dimension += 1;
+		return owen_sobol(self.index, seed);
+	}
+}
+
+pub struct BlueNoiseSampler{
+	seed:u64,
+	pixel:(u32, u32),
+	index:u32,
+	dimension:u32,
+}
+
+impl BlueNoiseSampler{
+	//Toroidal shift of the mask, a different window of it for every dimension and axis
+	fn shift(&self, axis:u64) -> f64{
+		let offset = hash(hash(self.seed, self.dimension as u64), axis);
+		let x = (self.pixel.0 as u64 + offset % BLUE_NOISE_SIZE as u64) as usize % BLUE_NOISE_SIZE;
+		let y = (self.pixel.1 as u64 + (offset >> 32) % BLUE_NOISE_SIZE as u64) as usize % BLUE_NOISE_SIZE;
+		return blue_noise_mask()[y * BLUE_NOISE_SIZE + x];
+	}
+}
+
+impl Sampler for BlueNoiseSampler{
+	fn start_pixel_sample(&mut self, pixel:(u32, u32), index:u32){
+		self.pixel = pixel;
+		self.index = index;
+		self.dimension = 0;
+	}
+
+	fn get_1d(&mut self) -> f64{
+		let (x, _) = owen_sobol(self.index, hash(self.seed, self.dimension as u64));
+		let shifted = (x + self.shift(0)).fract();
+		self.dimension += 1;
+		return shifted;
+	}
+
+	fn get_2d(&mut self) -> (f64, f64){
+		let (x, y) = owen_sobol(self.index, hash(self.seed, self.dimension as u64));
+		let shifted = ((x + self.shift(0)).fract(), (y + self.shift(1)).fract());
+		self.dimension += 1;
+		return shifted;
+	}
+}
+
+//First two Sobol dimensions of a shuffled index, both Owen scrambled
+fn owen_sobol(index:u32, seed:u64) -> (f64, f64){
+	let index = nested_uniform_scramble(index, seed as u32);
+
+	//The second dimension's direction numbers follow from the polynomial x + 1
+	let mut direction:u32 = 1 << 31;
+	let mut y = 0;
+	let mut bits = index;
+	while bits != 0{
+		if bits & 1 == 1{
+			y ^= direction;
+		}
+		bits >>= 1;
+		direction ^= direction >> 1;
+	}
+
+	let x = nested_uniform_scramble(index.reverse_bits(), (seed >> 32) as u32);
+	let y = nested_uniform_scramble(y, mix_bits(seed) as u32);
+	return (x as f64 / 4294967296.0, y as f64 / 4294967296.0);
+}
+
+//Owen scrambling, each bit is flipped by a hash of the bits above it
+fn nested_uniform_scramble(x:u32, seed:u32) -> u32{
+	return laine_karras_permutation(x.reverse_bits(), seed).reverse_bits();
+}
+
+fn laine_karras_permutation(mut x:u32, seed:u32) -> u32{
+	x = x.wrapping_add(seed);
+	x ^= x.wrapping_mul(0x6c50b47c);
+	x ^= x.wrapping_mul(0xb82f1e52);
+	x ^= x.wrapping_mul(0xc7afe638);
+	x ^= x.wrapping_mul(0x8d22f6e6);
+	return x;
+}
+
+//i-th element of a random permutation of 0..n picked by seed, see Kensler "Correlated Multi-Jittered Sampling"
+fn permutation_element(mut i:u32, n:u32, seed:u32) -> u32{
+	let mut w = n - 1;
+	w |= w >> 1;
+	w |= w >> 2;
+	w |= w >> 4;
+	w |= w >> 8;
+	w |= w >> 16;
+
+	loop{
+		i ^= seed;
+		i = i.wrapping_mul(0xe170893d);
+		i ^= seed >> 16;
+		i ^= (i & w) >> 4;
+		i ^= seed >> 8;
+		i = i.wrapping_mul(0x0929eb3f);
+		i ^= seed >> 23;
+		i ^= (i & w) >> 1;
+		i = i.wrapping_mul(1 | seed >> 27);
+		i = i.wrapping_mul(0x6935fa69);
+		i ^= (i & w) >> 11;
+		i = i.wrapping_mul(0x74dcb303);
+		i ^= (i & w) >> 2;
+		i = i.wrapping_mul(0x9e501cc3);
+		i ^= (i & w) >> 2;
+		i = i.wrapping_mul(0xc860a3df);
+		i &= w;
+		i ^= i >> 5;
+		if i < n{
+			break;
+		}
+	}
+	return i.wrapping_add(seed) % n;
+}
+
+fn pixel_seed(seed:u64, pixel:(u32, u32)) -> u64{
+	return hash(seed, hash(pixel.0 as u64, pixel.1 as u64));
+}
+
+fn hash(a:u64, b:u64) -> u64{
+	return mix_bits(a ^ mix_bits(b.wrapping_add(0x9e3779b97f4a7c15)));
+}
+
+//Finalizer of splitmix64
+fn mix_bits(mut v:u64) -> u64{
+	v ^= v >> 31;
+	v = v.wrapping_mul(0x7fb5d329728ea185);
+	v ^= v >> 27;
+	v = v.wrapping_mul(0x81dadef4bc2dd44d);
+	v ^= v >> 33;
+	return v;
+}
+
+fn to_unit(bits:u64) -> f64{
+	return (bits >> 11) as f64 / 9007199254740992.0;
+}
+
+const BLUE_NOISE_SIZE:usize = 64;
+
+//Ranks of a tileable blue noise mask mapped to (0, 1), built once on first use
+fn blue_noise_mask() -> &'static [f64]{
+	static MASK:OnceLock<Vec<f64>> = OnceLock::new();
+	return MASK.get_or_init(void_and_cluster);
+}
+
+//Ulichney's void and cluster method. The energy of a pixel is a gaussian of its toroidal distance to
+//every set pixel, the tightest cluster is the set pixel with the most energy and the largest void the
+//free pixel with the least.
+fn void_and_cluster() -> Vec<f64>{
+	const SIGMA:f64 = 1.5;
+	let size = BLUE_NOISE_SIZE;
+	let count = size * size;
+
+	let mut kernel = vec![0.0; count];
+	for dy in 0..size{
+		for dx in 0..size{
+			let x = dx.min(size - dx) as f64;
+			let y = dy.min(size - dy) as f64;
+			kernel[dy * size + dx] = (-(x * x + y * y) / (2.0 * SIGMA * SIGMA)).exp();
+		}
+	}
+
+	let update = |energy:&mut [f64], pixel:usize, sign:f64| {
+		let (px, py) = (pixel % size, pixel / size);
+		for y in 0..size{
+			for x in 0..size{
+				let dx = (x + size - px) % size;
+				let dy = (y + size - py) % size;
+				energy[y * size + x] += sign * kernel[dy * size + dx];
+			}
+		}
+	};
+	let tightest_cluster = |energy:&[f64], set:&[bool]| {
+		(0..count).filter(|&i| set[i]).max_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+	};
+	let largest_void = |energy:&[f64], set:&[bool]| {
+		(0..count).filter(|&i| !set[i]).min_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+	};
+
+	//Initial pattern of a tenth of the pixels at hashed positions
+	let mut set = vec![false; count];
+	let mut energy = vec![0.0; count];
+	let mut placed = 0;
+	let mut key = 0;
+	while placed < count / 10{
+		let pixel = (mix_bits(key) % count as u64) as usize;
+		key += 1;
+		if !set[pixel]{
+			set[pixel] = true;
+			update(&mut energy, pixel, 1.0);
+			placed += 1;
+		}
+	}
+
+	//Spread it out by moving the tightest cluster into the largest void until nothing moves
+	loop{
+		let cluster = tightest_cluster(&energy, &set);
+		set[cluster] = false;
+		update(&mut energy, cluster, -1.0);
+		let void = largest_void(&energy, &set);
+		set[void] = true;
+		update(&mut energy, void, 1.0);
+		if void == cluster{
+			break;
+		}
+	}
+
+	let mut ranks = vec![0; count];
+
+	//The initial pixels are ranked by removing clusters one by one
+	let mut removing_set = set.clone();
+	let mut removing_energy = energy.clone();
+	for rank in (0..placed).rev(){
+		let cluster = tightest_cluster(&removing_energy, &removing_set);
+		removing_set[cluster] = false;
+		update(&mut removing_energy, cluster, -1.0);
+		ranks[cluster] = rank;
+	}
+
+	//The rest by filling voids, with a linear energy the largest void of the set pixels is also the
+	//tightest cluster of the free ones so this covers the majority phase as well
+	for rank in placed..count{
+		let void = largest_void(&energy, &set);
+		set[void] = true;
+		update(&mut energy, void, 1.0);
+		ranks[void] = rank;
+	}
+
+	return ranks.iter().map(|&rank| (rank as f64 + 0.5) / count as f64).collect();
+}
+
+#[cfg(test)]
+mod tests{
+	use super::*;
+
+	const KINDS:[SamplerKind; 5] = [
+		SamplerKind::Independent,
+		SamplerKind::Stratified{strata:4},
+		SamplerKind::Halton,
+		SamplerKind::Sobol,
+		SamplerKind::BlueNoise,
+	];
+
+	//Values of a pixel sample, alternating 1d and 2d dimensions past the Halton primes
+	fn draw(sampler:&mut dyn Sampler, pixel:(u32, u32), index:u32) -> Vec<f64>{
+		sampler.start_pixel_sample(pixel, index);
+		let mut values = Vec::new();
+		for dimension in 0..80{
+			if dimension % 2 == 0{
+				values.push(sampler.get_1d());
+			}else{
+				let (x, y) = sampler.get_2d();
+				values.push(x);
+				values.push(y);
+			}
+		}
+		return values;
+	}
+
+	#[test]
+	fn samples_are_in_unit_interval(){
+		for kind in KINDS{
+			let mut sampler = kind.create(7);
+			for pixel in [(0, 0), (1, 0), (63, 64), (1919, 1079)]{
+				for index in 0..256{
+					for value in draw(sampler.as_mut(), pixel, index){
+						assert!((0.0..1.0).contains(&value), "{:?} {}", kind, value);
+					}
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn samples_are_deterministic(){
+		for kind in KINDS{
+			let mut sampler = kind.create(7);
+			let first = draw(sampler.as_mut(), (3, 5), 9);
+			//Other pixels and indices in between leave no trace
+			draw(sampler.as_mut(), (4, 5), 9);
+			draw(sampler.as_mut(), (3, 5), 10);
+			assert_eq!(first, draw(sampler.as_mut(), (3, 5), 9), "{:?}", kind);
+			assert_eq!(first, draw(kind.create(7).as_mut(), (3, 5), 9), "{:?}", kind);
+			assert_ne!(first, draw(kind.create(8).as_mut(), (3, 5), 9), "{:?}", kind);
+		}
+	}
+
+	//The first 2^k points of every dimension put one point in each of the 2^k intervals
+	#[test]
+	fn sobol_first_points_are_stratified(){
+		let mut sampler = SamplerKind::Sobol.create(7);
+		for pixel in [(0, 0), (17, 3)]{
+			for k in 0..=10{
+				let count = 1u32 << k;
+				let points:Vec<Vec<f64>> = (0..count).map(|index| draw(sampler.as_mut(), pixel, index)).collect();
+				for column in 0..points[0].len(){
+					let mut strata:Vec<u32> = points.iter().map(|values| (values[column] * count as f64) as u32).collect();
+					strata.sort();
+					assert!(strata.iter().copied().eq(0..count), "k {} column {}", k, column);
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,243 @@
+use crate::vector3::Vector3;
+use std::f64::consts::PI;
+
+//Warps of uniform numbers in [0, 1) to points and directions. Every sample comes with its pdf, in solid
+//angle for directions and in area for points. Directions are local to +z, move them with orthonormal_basis.
+
+//Two unit vectors completing n to an orthonormal basis, see Duff et al. "Building an Orthonormal Basis, Revisited"
+pub fn orthonormal_basis(n:&Vector3) -> (Vector3, Vector3){
+	let sign = 1.0_f64.copysign(n.z);
+	let a = -1.0 / (sign + n.z);
+	let b = n.x * n.y * a;
+	return (Vector3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x), Vector3::new(b, sign + n.y * n.y * a, -n.y));
+}
+
+//Local direction around +z moved to the basis around n
+pub fn to_basis(n:&Vector3, local:&Vector3) -> Vector3{
+	let (tangent, bitangent) = orthonormal_basis(n);
+	return local.x * tangent + local.y * bitangent + local.z * n;
+}
+
+pub fn uniform_sphere(u1:f64, u2:f64) -> (Vector3, f64){
+	let z = 1.0 - 2.0 * u1;
+	let r = (1.0 - z * z).max(0.0).sqrt();
+	let phi = 2.0 * PI * u2;
+	return (Vector3::new(r * phi.cos(), r * phi.sin(), z), uniform_sphere_pdf());
+}
+
+pub fn uniform_sphere_pdf() -> f64{
+	return 1.0 / (4.0 * PI);
+}
+
+pub fn uniform_hemisphere(u1:f64, u2:f64) -> (Vector3, f64){
+	let z = u1;
+	let r = (1.0 - z * z).max(0.0).sqrt();
+	let phi = 2.0 * PI * u2;
+	return (Vector3::new(r * phi.cos(), r * phi.sin(), z), uniform_hemisphere_pdf());
+}
+
+pub fn uniform_hemisphere_pdf() -> f64{
+	return 1.0 / (2.0 * PI);
+}
+
+//Point in the unit disk, Shirley and Chiu's concentric mapping keeps strata compact
+pub fn uniform_disk(u1:f64, u2:f64) -> ((f64, f64), f64){
+	let a = 2.0 * u1 - 1.0;
+	let b = 2.0 * u2 - 1.0;
+	if a == 0.0 && b == 0.0{
+		return ((0.0, 0.0), uniform_disk_pdf());
+	}
+
+	let (r, theta) = if a.abs() > b.abs(){
+		(a, PI / 4.0 * (b / a))
+	}else{
+		(b, PI / 2.0 - PI / 4.0 * (a / b))
+	};
+	return ((r * theta.cos(), r * theta.sin()), uniform_disk_pdf());
+}
+
+pub fn uniform_disk_pdf() -> f64{
+	return 1.0 / PI;
+}
+
+//Cosine weighted direction, the disk lifted onto the hemisphere (Malley's method)
+pub fn cosine_hemisphere(u1:f64, u2:f64) -> (Vector3, f64){
+	let ((x, y), _) = uniform_disk(u1, u2);
+	let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+	return (Vector3::new(x, y, z), cosine_hemisphere_pdf(z));
+}
+
+pub fn cosine_hemisphere_pdf(cos:f64) -> f64{
+	return cos.max(0.0) / PI;
+}
+
+//Direction within the cone of cos_max around +z, as seen by a point looking at a sphere
+pub fn uniform_cone(cos_max:f64, u1:f64, u2:f64) -> (Vector3, f64){
+	let z = 1.0 - u1 * (1.0 - cos_max);
+	let r = (1.0 - z * z).max(0.0).sqrt();
+	let phi = 2.0 * PI * u2;
+	return (Vector3::new(r * phi.cos(), r * phi.sin(), z), uniform_cone_pdf(cos_max));
+}
+
+pub fn uniform_cone_pdf(cos_max:f64) -> f64{
+	return 1.0 / (2.0 * PI * (1.0 - cos_max));
+}
+
+//Barycentric coordinates (u, v) of a uniform point, the point itself is (1 - u - v) * a + u * b + v * c
+pub fn uniform_triangle_barycentrics(u1:f64, u2:f64) -> (f64, f64){
+	let s = u1.sqrt();
+	return (s * (1.0 - u2), s * u2);
+}
+
+//Uniform point on the triangle with its barycentrics, the pdf is per unit area
+pub fn uniform_triangle(a:&Vector3, b:&Vector3, c:&Vector3, u1:f64, u2:f64) -> (Vector3, (f64, f64), f64){
+	let (u, v) = uniform_triangle_barycentrics(u1, u2);
+	let point = (1.0 - u - v) * a + u * b + v * c;
+	return (point, (u, v), uniform_triangle_pdf(a, b, c));
+}
+
+pub fn uniform_triangle_pdf(a:&Vector3, b:&Vector3, c:&Vector3) -> f64{
+	let area = 0.5 * (b - *a).cross(&(c - *a)).norm();
+	return 1.0 / area;
+}
+
+//Multiple importance sampling weight of a sample drawn with pdf when another strategy could draw it with
+//other_pdf, the power heuristic with beta = 2 from Veach's thesis
+pub fn power_heuristic(pdf:f64, other_pdf:f64) -> f64{
+	let a = pdf * pdf;
+	let b = other_pdf * other_pdf;
+	if a + b <= 0.0{
+		return 0.0;
+	}
+	return a / (a + b);
+}
+
+#[cfg(test)]
+mod tests{
+	use super::*;
+	use rand::{Rng, SeedableRng};
+	use rand::rngs::StdRng;
+
+	const COUNT:usize = 400000;
+
+	//Share of samples in cells of z from z_min to 1 and of the angle around z, against the integral
+	//of pdf over each cell. The returned pdf must match pdf too.
+	fn check_histogram<S:Fn(f64, f64) -> (Vector3, f64), P:Fn(&Vector3) -> f64>(z_min:f64, sample:S, pdf:P){
+		const CELLS:usize = 8;
+		const STEPS:usize = 32;
+		let cell = |z:f64, phi:f64| {
+			let i = (((z - z_min) / (1.0 - z_min) * CELLS as f64) as usize).min(CELLS - 1);
+			let j = ((phi.rem_euclid(2.0 * PI) / (2.0 * PI) * CELLS as f64) as usize).min(CELLS - 1);
+			i * CELLS + j
+		};
+
+		let mut rng = StdRng::seed_from_u64(0);
+		let mut histogram = vec![0.0; CELLS * CELLS];
+		let mut total = 0.0;
+		for _ in 0..COUNT{
+			let (dir, density) = sample(rng.gen(), rng.gen());
+			assert!((density - pdf(&dir)).abs() <= 1e-9 * density, "{} {}", density, pdf(&dir));
+			histogram[cell(dir.z, dir.y.atan2(dir.x))] += 1.0 / COUNT as f64;
+		}
+
+		//Midpoint rule in z and the angle, both uniform in solid angle
+		let mut expected = vec![0.0; CELLS * CELLS];
+		let dz = (1.0 - z_min) / (CELLS * STEPS) as f64;
+		let dphi = 2.0 * PI / (CELLS * STEPS) as f64;
+		for i in 0..CELLS * STEPS{
+			let z = z_min + (i as f64 + 0.5) * dz;
+			let r = (1.0 - z * z).sqrt();
+			for j in 0..CELLS * STEPS{
+				let phi = (j as f64 + 0.5) * dphi;
+				let value = pdf(&Vector3::new(r * phi.cos(), r * phi.sin(), z)) * dz * dphi;
+				expected[cell(z, phi)] += value;
+				total += value;
+			}
+		}
+
+		assert!((total - 1.0).abs() < 1e-3, "pdf integrates to {}", total);
+		for (observed, expected) in histogram.iter().zip(expected.iter()){
+			assert!((observed - expected).abs() < 2e-3, "{} {}", observed, expected);
+		}
+	}
+
+	fn check_unit<S:Fn(f64, f64) -> (Vector3, f64)>(sample:S){
+		let mut rng = StdRng::seed_from_u64(1);
+		for _ in 0..10000{
+			let (dir, _) = sample(rng.gen(), rng.gen());
+			assert!((dir.norm() - 1.0).abs() < 1e-9);
+		}
+		//Corners of the unit square
+		for (u1, u2) in [(0.0, 0.0), (0.0, 0.99999), (0.99999, 0.0), (0.99999, 0.99999), (0.5, 0.5)]{
+			let (dir, _) = sample(u1, u2);
+			assert!((dir.norm() - 1.0).abs() < 1e-9);
+		}
+	}
+
+	#[test]
+	fn uniform_sphere_matches_pdf(){
+		check_unit(uniform_sphere);
+		check_histogram(-1.0, uniform_sphere, |_| uniform_sphere_pdf());
+	}
+
+	#[test]
+	fn uniform_hemisphere_stays_above(){
+		check_unit(uniform_hemisphere);
+		check_histogram(0.0, uniform_hemisphere, |dir| if dir.z >= 0.0 {uniform_hemisphere_pdf()} else {0.0});
+		//Nothing lands below, the histogram would put it in the lowest cell
+		let mut rng = StdRng::seed_from_u64(2);
+		assert!((0..COUNT).all(|_| uniform_hemisphere(rng.gen(), rng.gen()).0.z >= 0.0));
+	}
+
+	#[test]
+	fn cosine_hemisphere_matches_pdf(){
+		check_unit(cosine_hemisphere);
+		check_histogram(0.0, cosine_hemisphere, |dir| cosine_hemisphere_pdf(dir.z));
+		let mut rng = StdRng::seed_from_u64(2);
+		assert!((0..COUNT).all(|_| cosine_hemisphere(rng.gen(), rng.gen()).0.z >= 0.0));
+	}
+
+	#[test]
+	fn uniform_cone_matches_pdf(){
+		for cos_max in [-0.5, 0.0, 0.8, 0.999]{
+			let sample = |u1, u2| uniform_cone(cos_max, u1, u2);
+			check_unit(sample);
+			check_histogram(cos_max, sample, |dir| if dir.z >= cos_max {uniform_cone_pdf(cos_max)} else {0.0});
+			let mut rng = StdRng::seed_from_u64(2);
+			assert!((0..COUNT).all(|_| sample(rng.gen(), rng.gen()).0.z >= cos_max - 1e-12));
+		}
+	}
+
+	//Midpoints of the edges of an n by n grid of the barycentrics split the triangle into n^2 triangles
+	//of the same area, each should get the same share
+	#[test]
+	fn uniform_triangle_matches_pdf(){
+		const N:usize = 8;
+		let a = Vector3::new(1.0, 0.0, 0.0);
+		let b = Vector3::new(3.0, 1.0, 0.0);
+		let c = Vector3::new(0.0, 2.0, 2.0);
+		let area = 0.5 * (b - a).cross(&(c - a)).norm();
+		let normal = (b - a).cross(&(c - a)).normalize();
+
+		let mut rng = StdRng::seed_from_u64(3);
+		let mut histogram = vec![0.0; N * N];
+		for _ in 0..COUNT{
+			let (point, (u, v), pdf) = uniform_triangle(&a, &b, &c, rng.gen(), rng.gen());
+			assert!((pdf * area - 1.0).abs() < 1e-12);
+			assert!(u >= 0.0 && v >= 0.0 && u + v <= 1.0 + 1e-12);
+			assert!((point - ((1.0 - u - v) * a + u * b + v * c)).norm() < 1e-12);
+			assert!((point - a).dot(&normal).abs() < 1e-12);
+
+			let (su, sv) = (u * N as f64, v * N as f64);
+			let (i, j) = ((su as usize).min(N - 1), (sv as usize).min(N - 1));
+			//Row j holds 2 (N - j) - 1 triangles, the upper ones of a square come after the lower ones
+			let upper = su - i as f64 + sv - j as f64 >= 1.0;
+			let row:usize = (0..j).map(|row| 2 * (N - row) - 1).sum();
+			let index = row + if upper {N - j + i} else {i};
+			histogram[index] += 1.0 / COUNT as f64;
+		}
+		for share in histogram{
+			assert!((share - 1.0 / (N * N) as f64).abs() < 1e-3, "{}", share);
+		}
+	}
+}
@@ -4,15 +4,38 @@
 //use na::{Vector3};
 use crate::vector3::Vector3;
 use std::collections::HashMap;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 
 pub mod objects;
 pub mod lights;
 pub mod materials;
+pub mod obj;
+pub mod textures;
+pub mod noise;
+pub mod microfacet;
+pub mod bsdf;
+pub mod normal_map;
 
 use objects::Object;
 use lights::Light;
+use materials::MaterialId;
+use textures::{Texture, Mapping, Pattern};
+use microfacet::Conductor;
+use normal_map::NormalMap;
+use crate::spectrum::Dispersion;
 
-use self::objects::Sphere;
+//Random placement of the generated scenes, fixed so they are the same on every run
+const SCENE_SEED:u64 = 0;
+
+//Scene::new always stores materials::BASE_MATERIAL first
+const BASE_MATERIAL_ID:MaterialId = MaterialId(0);
+
+use self::objects::{ObjectHandle, Sphere, Shape, Mesh, Plane, Quad, Cuboid, Cylinder, Cone, Disk, Capsule, Instance};
+use crate::matrix4::Transform;
+use std::sync::Arc;
+use std::f64::consts::PI;
+use crate::bvh::{Bvh, Aabb};
 
 pub struct Camera{
     pub position:Vector3,
@@ -79,39 +102,178 @@ impl Camera{
 
 pub struct Scene{
     pub main_camera:Camera,
-    pub objects:Vec<Object>,
     pub lights:Vec<Light>,
     pub gradient_light_1:Vector3,
     pub gradient_light_2:Vector3,
 
-    pub materials:HashMap<String, materials::Material>,
-    pub spheres:Vec<Sphere>,
+    //Materials are shared by id, editing one here changes every object using it
+    materials:Vec<materials::Material>,
+    material_names:HashMap<String, MaterialId>,
+
+    //Single primitive table, only reachable through ObjectHandle so geometry edits always reach the bvh
+    objects:Vec<Object>,
+    bvh:Bvh,
+    bvh_dirty:bool,
 }
 
 impl Scene{
     pub fn new(camera:Camera) -> Scene{
         return Scene{
             main_camera:camera,
-            objects:Vec::new(),
             lights:Vec::new(),
             gradient_light_1:Vector3::new(0.67, 0.84, 0.97),
             gradient_light_2:Vector3::new(0.57, 0.63, 0.70),
-            materials:HashMap::new(),
-            spheres:Vec::new(),
+            materials:vec![materials::BASE_MATERIAL.clone()],
+            material_names:HashMap::new(),
+            objects:Vec::new(),
+            bvh:Bvh::empty(),
+            bvh_dirty:false,
         };
     }
 
-    pub fn add_material(&mut self, name:String, material:materials::Material){
-        self.materials.insert(name, material);
+    //Adding a material with an existing name replaces it in place, objects keep using the same id
+    pub fn add_material(&mut self, name:String, material:materials::Material) -> MaterialId{
+        if let Some(id) = self.material_names.get(&name){
+            self.materials[id.index()] = material;
+            return *id;
+        }
+
+        self.materials.push(material);
+        let id = MaterialId(self.materials.len() as u32 - 1);
+        self.material_names.insert(name, id);
+        return id;
+    }
+
+    //Registers a user implemented material under a name
+    pub fn add_custom_material(&mut self, name:String, bsdf:Arc<dyn bsdf::Bsdf>) -> MaterialId{
+        return self.add_material(name, materials::Material::Custom(bsdf));
+    }
+
+    //Unknown names resolve to the base material
+    pub fn get_material(&self, material: &String) -> MaterialId {
+        return *self.material_names.get(material).unwrap_or(&BASE_MATERIAL_ID);
+    }
+
+    pub fn material(&self, id:MaterialId) -> &materials::Material{
+        return &self.materials[id.index()];
+    }
+
+    pub fn material_mut(&mut self, id:MaterialId) -> &mut materials::Material{
+        return &mut self.materials[id.index()];
+    }
+
+    pub fn material_by_name_mut(&mut self, name: &String) -> Option<&mut materials::Material>{
+        let id = *self.material_names.get(name)?;
+        return Some(self.material_mut(id));
+    }
+
+    pub fn create_sphere(&mut self, pos:Vector3, radius:f64, material: &String) -> ObjectHandle{
+        return self.add_object(Sphere::create(pos, radius, self.get_material(material)));
+    }
+
+    pub fn create_plane(&mut self, pos:Vector3, normal:Vector3, material: &String) -> ObjectHandle{
+        return self.add_object(Plane::create(pos, normal, self.get_material(material)));
     }
 
-    pub fn get_material(&self, material: &String) -> &materials::Material {
-        return self.materials.get(material).unwrap_or(&materials::BASE_MATERIAL);
+    pub fn create_quad(&mut self, corner:Vector3, u:Vector3, v:Vector3, material: &String) -> ObjectHandle{
+        return self.add_object(Quad::create(corner, u, v, self.get_material(material)));
     }
 
-    pub fn create_sphere(&mut self, pos:Vector3, radius:f64, material: &String){
-        self.objects.push(objects::Sphere::create(pos, radius, *self.get_material(material)));
-        self.spheres.push(Sphere::create_sphere(pos, radius));
+    pub fn create_cuboid(&mut self, min:Vector3, max:Vector3, material: &String) -> ObjectHandle{
+        return self.add_object(Cuboid::create(min, max, self.get_material(material)));
+    }
+
+    pub fn create_cylinder(&mut self, base:Vector3, axis:Vector3, radius:f64, height:f64, material: &String) -> ObjectHandle{
+        return self.add_object(Cylinder::create(base, axis, radius, height, self.get_material(material)));
+    }
+
+    pub fn create_cone(&mut self, base:Vector3, axis:Vector3, radius:f64, height:f64, material: &String) -> ObjectHandle{
+        return self.add_object(Cone::create(base, axis, radius, height, self.get_material(material)));
+    }
+
+    pub fn create_disk(&mut self, pos:Vector3, normal:Vector3, radius:f64, material: &String) -> ObjectHandle{
+        return self.add_object(Disk::create(pos, normal, radius, self.get_material(material)));
+    }
+
+    pub fn create_capsule(&mut self, a:Vector3, b:Vector3, radius:f64, material: &String) -> ObjectHandle{
+        return self.add_object(Capsule::create(a, b, radius, self.get_material(material)));
+    }
+
+    pub fn create_mesh(&mut self, mesh:Mesh, material: &String) -> ObjectHandle{
+        return self.add_object(Mesh::create(mesh, self.get_material(material)));
+    }
+
+    //Places a shared shape, every instance of the same Arc uses one copy of the geometry
+    pub fn create_instance(&mut self, shape:&Arc<Shape>, transform:Transform, material: &String) -> ObjectHandle{
+        return self.add_object(Instance::create(shape.clone(), transform, self.get_material(material)));
+    }
+
+    pub fn create_transformed(&mut self, shape:Shape, transform:Transform, material: &String) -> ObjectHandle{
+        return self.create_instance(&Arc::new(shape), transform, material);
+    }
+
+    pub fn add_object(&mut self, object:Object) -> ObjectHandle{
+        self.objects.push(object);
+        self.bvh_dirty = true;
+        return ObjectHandle(self.objects.len() as u32 - 1);
+    }
+
+    pub fn object(&self, handle:ObjectHandle) -> &Object{
+        return &self.objects[handle.index()];
+    }
+
+    pub fn objects(&self) -> &[Object]{
+        return &self.objects;
+    }
+
+    pub fn object_count(&self) -> usize{
+        return self.objects.len();
+    }
+
+    //Handles in creation order
+    pub fn handles(&self) -> impl Iterator<Item = ObjectHandle>{
+        return (0..self.objects.len() as u32).map(ObjectHandle);
+    }
+
+    pub fn shape(&self, handle:ObjectHandle) -> &Shape{
+        return &self.objects[handle.index()].shape;
+    }
+
+    //Geometry changes invalidate the bvh until update_bvh is called, render calls it itself
+    pub fn shape_mut(&mut self, handle:ObjectHandle) -> &mut Shape{
+        self.bvh_dirty = true;
+        return &mut self.objects[handle.index()].shape;
+    }
+
+    pub fn set_shape(&mut self, handle:ObjectHandle, shape:Shape){
+        *self.shape_mut(handle) = shape;
+    }
+
+    pub fn set_material(&mut self, handle:ObjectHandle, material: &String){
+        self.objects[handle.index()].material = self.get_material(material);
+    }
+
+    //Raycasts through a stale bvh would miss added or moved objects, call update_bvh after editing
+    pub fn bvh(&self) -> &Bvh{
+        debug_assert!(!self.bvh_dirty, "scene geometry changed since the bvh was built, call update_bvh");
+        return &self.bvh;
+    }
+
+    pub fn is_bvh_dirty(&self) -> bool{
+        return self.bvh_dirty;
+    }
+
+    pub fn build_bvh(&mut self){
+        let bounds:Vec<Aabb> = self.objects.iter().map(|o| o.shape.bounds()).collect();
+        self.bvh = Bvh::build(&bounds);
+        self.bvh_dirty = false;
+    }
+
+    //Rebuilds the bvh only if objects were added or their geometry changed
+    pub fn update_bvh(&mut self){
+        if self.bvh_dirty{
+            self.build_bvh();
+        }
     }
 }
 
@@ -124,9 +286,9 @@ pub fn load_scene() -> Scene{
     let mut scene:Scene = Scene::new(main_camera);
 
     //Create lights
-    scene.lights.push(lights::PointLight::create(Vector3::new(0.0, 5.0, -4.0), Vector3::new(1.0, 0.0, 0.0)));
-    scene.lights.push(lights::PointLight::create(Vector3::new(0.0, 5.0, 0.0), Vector3::new(0.0, 1.0, 0.0)));
-    scene.lights.push(lights::PointLight::create(Vector3::new(0.0, 5.0, 4.0), Vector3::new(0.0, 0.0, 1.0)));
+    scene.lights.push(lights::PointLight::create(Vector3::new(0.0, 5.0, -4.0), PI * Vector3::new(1.0, 0.0, 0.0)));
+    scene.lights.push(lights::PointLight::create(Vector3::new(0.0, 5.0, 0.0), PI * Vector3::new(0.0, 1.0, 0.0)));
+    scene.lights.push(lights::PointLight::create(Vector3::new(0.0, 5.0, 4.0), PI * Vector3::new(0.0, 0.0, 1.0)));
 
     load_materials(&mut scene);
 
@@ -179,6 +341,7 @@ pub fn load_scene() -> Scene{
     scene.create_sphere(portal_a_pos, 2.0, &String::from("portal_a"));
     scene.create_sphere(portal_b_pos, 2.0, &String::from("portal_b"));
 
+    scene.build_bvh();
     return scene;
 }
 
@@ -192,7 +355,7 @@ pub fn load_scene_2() -> Scene{
     load_materials(&mut scene);
 
     //Create lights
-    scene.lights.push(lights::PointLight::create(Vector3::new(0.0, 5.0, 0.0), Vector3::new(30.3, 10.3, 10.3)));
+    scene.lights.push(lights::PointLight::create(Vector3::new(0.0, 5.0, 0.0), PI * Vector3::new(30.3, 10.3, 10.3)));
     //scene.lights.push(lights::PointLight::create(Vector3::new(0.0, 5.0, 0.0), Vector3::new(0.0, 1.0, 0.0)));
     //scene.lights.push(lights::PointLight::create(Vector3::new(0.0, 5.0, 4.0), Vector3::new(0.0, 0.0, 1.0)));
 
@@ -205,11 +368,11 @@ pub fn load_scene_2() -> Scene{
     /////////Create objects
 
     //Diffuse
-    scene.create_sphere(Vector3::new(0.0, -10000.0, 0.0), 10000.0, &String::from("diffuse_white"));
-    scene.create_sphere(Vector3::new(0.0, 10010.0, 0.0), 10000.0, &String::from("diffuse_white"));
-    scene.create_sphere(Vector3::new(0.0, 0.0, 10010.0), 10000.0, &String::from("diffuse_white"));
-    scene.create_sphere(Vector3::new(0.0, 0.0, -10010.0), 10000.0, &String::from("diffuse_white"));
-    scene.create_sphere(Vector3::new(-10010.0, 0.0, 0.0), 10000.0, &String::from("diffuse_white"));
+    scene.create_plane(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), &String::from("diffuse_white"));
+    scene.create_plane(Vector3::new(0.0, 10.0, 0.0), Vector3::new(0.0, -1.0, 0.0), &String::from("diffuse_white"));
+    scene.create_plane(Vector3::new(0.0, 0.0, 10.0), Vector3::new(0.0, 0.0, -1.0), &String::from("diffuse_white"));
+    scene.create_plane(Vector3::new(0.0, 0.0, -10.0), Vector3::new(0.0, 0.0, 1.0), &String::from("diffuse_white"));
+    scene.create_plane(Vector3::new(-10.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), &String::from("diffuse_white"));
 
     //Emission
     //scene.create_sphere(Vector3::new(0.0, 5.0, 0.0), 4.0, &String::from("emission_white"));
@@ -232,10 +395,81 @@ pub fn load_scene_2() -> Scene{
     //scene.create_sphere(portal_a_pos, 2.0, &String::from("portal_a"));
     //scene.create_sphere(portal_b_pos, 2.0, &String::from("portal_b"));
 
+    scene.build_bvh();
     return scene;
 
 }
 
+//Stress scene, a field of small random spheres on a floor
+pub fn load_scene_spheres(count:u32) -> Scene{
+    let main_camera = Camera::new(Vector3::new(60.0, 30.0, 60.0),
+        Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), 60.0);
+
+    let mut scene:Scene = Scene::new(main_camera);
+    load_materials(&mut scene);
+
+    scene.lights.push(lights::PointLight::create(Vector3::new(0.0, 40.0, 0.0), PI * Vector3::new(300.0, 300.0, 300.0)));
+
+    scene.create_plane(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), &String::from("diffuse_white"));
+
+    let material_names = ["diffuse_red", "diffuse_green", "diffuse_blue", "diffuse_yellow", "metal_silver", "glass_glass"];
+    let side = (count as f64).sqrt().ceil() as u32;
+    let spacing = 100.0 / side as f64;
+    let mut rng = StdRng::seed_from_u64(SCENE_SEED);
+
+    for i in 0..count{
+        let x = (i % side) as f64 * spacing - 50.0 + rng.gen::<f64>() * 0.5 * spacing;
+        let z = (i / side) as f64 * spacing - 50.0 + rng.gen::<f64>() * 0.5 * spacing;
+        let radius = (0.2 + 0.2 * rng.gen::<f64>()) * spacing;
+        let material = material_names[rng.gen_range(0..material_names.len())];
+        scene.create_sphere(Vector3::new(x, radius, z), radius, &String::from(material));
+    }
+
+    scene.build_bvh();
+    return scene;
+}
+
+//Instancing example, one cylinder shape placed many times with random rotation and non uniform scale
+pub fn load_scene_instances(count:u32) -> Scene{
+    let main_camera = Camera::new(Vector3::new(40.0, 25.0, 40.0),
+        Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), 60.0);
+
+    let mut scene:Scene = Scene::new(main_camera);
+    load_materials(&mut scene);
+
+    scene.lights.push(lights::PointLight::create(Vector3::new(0.0, 40.0, 0.0), PI * Vector3::new(300.0, 300.0, 300.0)));
+    scene.create_plane(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), &String::from("diffuse_white"));
+
+    let cylinder = Arc::new(Shape::Cylinder(Cylinder{
+        base:Vector3::new(0.0, -0.5, 0.0),
+        axis:Vector3::new(0.0, 1.0, 0.0),
+        radius:0.5,
+        height:1.0,
+    }));
+
+    let material_names = ["diffuse_red", "diffuse_green", "diffuse_blue", "diffuse_yellow", "metal_gray_fuzz"];
+    let side = (count as f64).sqrt().ceil() as u32;
+    let spacing = 60.0 / side as f64;
+    let mut rng = StdRng::seed_from_u64(SCENE_SEED);
+
+    for i in 0..count{
+        let x = (i % side) as f64 * spacing - 30.0;
+        let z = (i / side) as f64 * spacing - 30.0;
+        let scale = Vector3::new(0.3 + 0.4 * rng.gen::<f64>(), 0.5 + rng.gen::<f64>(), 0.3 + 0.4 * rng.gen::<f64>()) * spacing;
+        let axis = Vector3::new(rng.gen::<f64>() - 0.5, rng.gen::<f64>() - 0.5, rng.gen::<f64>() - 0.5);
+
+        let transform = Transform::scale(&scale)
+            .then(&Transform::rotation(&axis, rng.gen::<f64>() * std::f64::consts::PI))
+            .then(&Transform::translation(&Vector3::new(x, 0.5 * scale.y, z)));
+
+        let material = material_names[rng.gen_range(0..material_names.len())];
+        scene.create_instance(&cylinder, transform, &String::from(material));
+    }
+
+    scene.build_bvh();
+    return scene;
+}
+
 fn load_materials(scene: &mut Scene){
     ////////Create Materials
 
@@ -247,6 +481,7 @@ fn load_materials(scene: &mut Scene){
     scene.add_material(String::from("emission_red"), materials::Emission::create(Vector3::new(0.5, 0.5, 0.5), Vector3::new(0.95, 0.1, 0.1)));
     scene.add_material(String::from("emission_green"), materials::Emission::create(Vector3::new(0.5, 0.5, 0.5), Vector3::new(0.1, 0.95, 0.1)));
     scene.add_material(String::from("emission_blue"), materials::Emission::create(Vector3::new(0.5, 0.5, 0.5), Vector3::new(0.1, 0.1, 0.95)));
+    scene.add_material(String::from("emission_lava"), materials::Emission::create_textured(Texture::from(Vector3::new(0.5, 0.2, 0.1)), Texture::noise(Pattern::VoronoiEdges{jitter:1.0}, 2.0, Vector3::new(1.0, 0.5, 0.1), Vector3::new(0.05, 0.0, 0.0))));
 
     //Diffuse
     scene.add_material(String::from("diffuse_white"), materials::Diffuse::create(Vector3::new(0.9, 0.9, 0.9)));
@@ -256,6 +491,11 @@ fn load_materials(scene: &mut Scene){
     scene.add_material(String::from("diffuse_yellow"), materials::Diffuse::create(Vector3::new(0.9, 0.9, 0.0)));
     scene.add_material(String::from("diffuse_black"), materials::Diffuse::create(Vector3::new(0.1, 0.1, 0.1)));
     scene.add_material(String::from("diffuse_dark_gray"), materials::Diffuse::create(Vector3::new(0.3, 0.3, 0.3)));
+    scene.add_material(String::from("diffuse_checker"), materials::Diffuse::create_textured(Texture::checker(Vector3::new(0.9, 0.9, 0.9), Vector3::new(0.1, 0.1, 0.1), Mapping::scaled_uv(8.0, 8.0))));
+    scene.add_material(String::from("diffuse_marble"), materials::Diffuse::create_textured(Texture::noise(Pattern::Marble{octaves:6, distortion:5.0}, 1.0, Vector3::new(0.15, 0.15, 0.2), Vector3::new(0.9, 0.9, 0.85))));
+    scene.add_material(String::from("diffuse_wood"), materials::Diffuse::create_textured(Texture::noise(Pattern::Wood{rings:4.0, distortion:0.6}, 1.0, Vector3::new(0.45, 0.25, 0.1), Vector3::new(0.75, 0.5, 0.25))));
+    scene.add_material(String::from("diffuse_cells"), materials::Diffuse::create_textured(Texture::noise(Pattern::VoronoiCells{jitter:1.0}, 2.0, Vector3::new(0.1, 0.3, 0.8), Vector3::new(0.9, 0.8, 0.2))));
+    scene.add_material(String::from("diffuse_checker_triplanar"), materials::Diffuse::create_textured(Texture::checker(Vector3::new(0.9, 0.9, 0.9), Vector3::new(0.1, 0.1, 0.1), Mapping::triplanar(1.0))));
 
     //Metal
     scene.add_material(String::from("metal_red_fuzz"), materials::Metal::create(Vector3::new(1.0, 0.45, 0.45), 0.7));
@@ -265,10 +505,54 @@ fn load_materials(scene: &mut Scene){
     scene.add_material(String::from("metal_silver_fuzz_0.4"), materials::Metal::create(Vector3::new(0.9, 0.9, 0.9), 0.4));
     scene.add_material(String::from("metal_silver_fuzz_0.6"), materials::Metal::create(Vector3::new(0.9, 0.9, 0.9), 0.6));
     scene.add_material(String::from("metal_silver_fuzz_0.8"), materials::Metal::create(Vector3::new(0.9, 0.9, 0.9), 0.8));
+    scene.add_material(String::from("metal_gold"), materials::Metal::create_conductor(Conductor::GOLD, 0.3));
+    scene.add_material(String::from("metal_copper"), materials::Metal::create_conductor(Conductor::COPPER, 0.3));
+    scene.add_material(String::from("metal_aluminum"), materials::Metal::create_conductor(Conductor::ALUMINUM, 0.3));
+    scene.add_material(String::from("metal_silver_rough"), materials::Metal::create_conductor(Conductor::SILVER, 0.3));
+    scene.add_material(String::from("metal_silver_fuzz_noise"), materials::Metal::create_textured(Texture::from(Vector3::new(0.9, 0.9, 0.9)), Texture::noise(Pattern::Fbm{octaves:4}, 3.0, Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.6, 0.6, 0.6))));
+    scene.add_material(String::from("metal_brushed_aluminum"), materials::Metal::create_anisotropic(Conductor::ALUMINUM, 0.4, 0.9, 0.0));
+    scene.add_material(String::from("metal_anodized"), materials::Metal::create_thin_film(Conductor::ALUMINUM, 0.1, materials::ThinFilm::new(Texture::noise(Pattern::Fbm{octaves:3}, 1.0, Vector3::new(200.0, 200.0, 200.0), Vector3::new(500.0, 500.0, 500.0)), 2.0)));
+
+    //Principled
+    scene.add_material(String::from("principled_red_plastic"), materials::Material::Principled(materials::Principled{
+        roughness:Texture::from(0.4),
+        clearcoat:1.0,
+        ..materials::Principled::new(Texture::from(Vector3::new(0.8, 0.1, 0.1)))
+    }));
+    scene.add_material(String::from("principled_gold"), materials::Principled::create(Vector3::new(1.0, 0.78, 0.34), 1.0, 0.25));
+    scene.add_material(String::from("principled_velvet"), materials::Material::Principled(materials::Principled{
+        roughness:Texture::from(1.0),
+        sheen:1.0,
+        ..materials::Principled::new(Texture::from(Vector3::new(0.3, 0.05, 0.35)))
+    }));
+    scene.add_material(String::from("principled_glass"), materials::Material::Principled(materials::Principled{
+        roughness:Texture::from(0.0),
+        transmission:1.0,
+        ..materials::Principled::new(Texture::from(Vector3::new(0.9, 1.0, 0.95)))
+    }));
+
+    //Normal maps, bump heights go from 0 to 1 and strength scales the slope
+    scene.add_material(String::from("diffuse_bumpy"), materials::NormalMapped::create(materials::Diffuse::create(Vector3::new(0.8, 0.75, 0.7)), NormalMap::bump(Texture::noise(Pattern::Fbm{octaves:4}, 3.0, Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0)), 0.15)));
+    scene.add_material(String::from("metal_hammered_copper"), materials::NormalMapped::create(materials::Metal::create_conductor(Conductor::COPPER, 0.15), NormalMap::bump(Texture::noise(Pattern::Voronoi{jitter:1.0}, 4.0, Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0)), 0.1)));
+
+    //Subsurface, radius is the mean free path per channel in scene units. Milk uses the reduced
+    //coefficients of Jensen et al. 2001 in inverse millimeters, so one unit is a millimeter.
+    scene.add_material(String::from("subsurface_skin"), materials::Subsurface::create(Vector3::new(0.85, 0.55, 0.45), Vector3::new(0.6, 0.25, 0.15)));
+    scene.add_material(String::from("subsurface_wax"), materials::Subsurface::create(Vector3::new(0.95, 0.85, 0.6), Vector3::new(0.5, 0.4, 0.3)));
+    scene.add_material(String::from("subsurface_marble"), materials::Subsurface::create(Vector3::new(0.9, 0.9, 0.88), Vector3::new(0.2, 0.2, 0.2)));
+    scene.add_material(String::from("subsurface_milk"), materials::Subsurface::create_coefficients(Vector3::new(2.55, 3.21, 3.77), Vector3::new(0.0011, 0.0024, 0.014), 0.0));
+    scene.add_material(String::from("subsurface_wax_single"), materials::Subsurface::create_single_scatter(Vector3::new(0.95, 0.85, 0.6), Vector3::new(0.5, 0.4, 0.3)));
 
     //Glass
-    scene.add_material(String::from("glass_diamond"), materials::Glass::create(Vector3::new(1.0, 1.0, 1.0), 2.4));
+    scene.add_material(String::from("glass_diamond"), materials::Glass::create_dispersive(Vector3::new(1.0, 1.0, 1.0), Dispersion::DIAMOND, 0.0));
+    scene.add_material(String::from("glass_bk7"), materials::Glass::create_dispersive(Vector3::new(1.0, 1.0, 1.0), Dispersion::BK7, 0.0));
+    //Thin films, thickness in nanometers
+    scene.add_material(String::from("glass_soap_bubble"), materials::Glass::create_thin_film(1.0, 0.0, materials::ThinFilm::new(Texture::noise(Pattern::Turbulence{octaves:4}, 0.8, Vector3::new(250.0, 250.0, 250.0), Vector3::new(750.0, 750.0, 750.0)), 1.33)));
+    scene.add_material(String::from("glass_oil_slick"), materials::Glass::create_thin_film(1.33, 0.0, materials::ThinFilm::new(Texture::noise(Pattern::Fbm{octaves:4}, 0.5, Vector3::new(300.0, 300.0, 300.0), Vector3::new(900.0, 900.0, 900.0)), 1.45)));
     scene.add_material(String::from("glass_glass"), materials::Glass::create(Vector3::new(1.0, 1.0, 1.0), 1.8));
+    scene.add_material(String::from("glass_frosted"), materials::Glass::create_rough(Texture::from(1.0), 1.5, Texture::from(0.3)));
+    scene.add_material(String::from("glass_green_absorbing"), materials::Glass::create_absorbing(Vector3::new(0.3, 0.8, 0.4), 1.0, 1.5, 0.0));
+    scene.add_material(String::from("glass_sandblasted"), materials::Glass::create_rough(Texture::from(1.0), 1.5, Texture::from(0.6)));
     scene.add_material(String::from("glass_r_1.0"), materials::Glass::create(Vector3::new(1.0, 1.0, 1.0), 1.0));
     scene.add_material(String::from("glass_r_1.4"), materials::Glass::create(Vector3::new(1.0, 1.0, 1.0), 1.4));
     scene.add_material(String::from("glass_r_1.8"), materials::Glass::create(Vector3::new(1.0, 1.0, 1.0), 1.8));
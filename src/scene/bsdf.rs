@@ -0,0 +1,80 @@
+use crate::vector3::Vector3;
+use crate::intersection::HitData;
+use crate::spectrum::Wavelengths;
+use crate::sampler::Sampler;
+
+//Interface the renderer uses to shade a hit. Material implements it for the built in materials
+//and Material::Custom wraps any other implementation so new materials need no renderer changes.
+//
+//Directions follow the ray: dir_in is the direction of the incoming ray and dir_out points away
+//from the surface. Colors are in the space of the ray, values at the wavelengths in spectral mode
+//and RGB otherwise, spectrum::carried converts RGB colors for both cases.
+pub trait Bsdf: Send + Sync{
+	fn flags(&self, hit_data:&HitData) -> BsdfFlags;
+
+	//Reflected radiance factor, bsdf times the cosine of dir_out, used for light sampling
+	fn eval(&self, dir_in:&Vector3, dir_out:&Vector3, hit_data:&HitData, wavelengths:&Option<Wavelengths>) -> Vector3;
+
+	//Solid angle density of sample returning dir_out, 0 for delta lobes. wavelengths are the ones
+	//sample returned, materials whose sampling depends on them read the same ones here.
+	fn pdf(&self, dir_in:&Vector3, dir_out:&Vector3, hit_data:&HitData, wavelengths:&Option<Wavelengths>) -> f64;
+
+	//Chooses the next direction with numbers drawn from sampler, None absorbs the path. Dispersive
+	//materials may change the wavelengths.
+	fn sample(&self, dir_in:&Vector3, hit_data:&HitData, wavelengths:&mut Option<Wavelengths>, sampler:&mut dyn Sampler) -> Option<BsdfSample>;
+
+	//Moves the shading normal and tangent frame of the hit before it is shaded, for normal maps
+	fn perturb(&self, _hit_data:&mut HitData){
+	}
+
+	fn emitted(&self, _hit_data:&HitData, _wavelengths:&Option<Wavelengths>) -> Vector3{
+		return Vector3::new(0.0, 0.0, 0.0);
+	}
+
+	//Emits light over the whole surface, objects made of it are sampled as area lights
+	fn is_emitter(&self) -> bool{
+		return false;
+	}
+
+	//Attenuation of the segment that ended at hit_data, for hits from inside the object
+	fn transmittance(&self, _hit_data:&HitData, _wavelengths:&Option<Wavelengths>) -> Vector3{
+		return Vector3::new(1.0, 1.0, 1.0);
+	}
+
+	//Surface color in RGB, given to the denoiser
+	fn albedo(&self, hit_data:&HitData) -> Vector3;
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct BsdfFlags{
+	//Scatters into a single direction, eval is always 0 and light sampling is skipped
+	pub delta:bool,
+	pub emissive:bool,
+	//Can send light through the surface
+	pub transmissive:bool,
+}
+
+pub struct BsdfSample{
+	pub direction:Vector3,
+	//eval / pdf for the sampled direction, or the lobe weight of a delta lobe
+	pub weight:Vector3,
+	//Density pdf would give the direction, 0 for lobes that eval leaves out such as delta ones
+	pub pdf:f64,
+	pub delta:bool,
+	//Where the next ray starts when it is not the hit point
+	pub origin:Option<Vector3>,
+	pub lobe:BsdfLobe,
+}
+
+//Kind of bounce a sample took, the integrator limits the depth of each on its own
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BsdfLobe{
+	Diffuse,
+	//Smooth and rough reflection
+	Glossy,
+	//Refraction, or passing through a surface into the volume behind it
+	Transmission,
+	Portal,
+	//Scattering inside a volume, such as the random walk of subsurface materials
+	Volume,
+}
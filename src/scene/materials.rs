@@ -3,69 +3,202 @@
 //use na::Vector3;
 use crate::vector3::Vector3;
 
-use rand::Rng;
 use crate::intersection::HitData;
 use crate::intersection::Ray;
+use crate::spectrum::{self, Dispersion};
+use super::textures::Texture;
+use super::normal_map::NormalMap;
+use super::microfacet::{self, Ggx, Frame, Conductor, Complex};
+use super::bsdf::{Bsdf, BsdfFlags, BsdfSample, BsdfLobe};
+use crate::spectrum::Wavelengths;
+use crate::sampling;
+use crate::sampler::Sampler;
+use std::sync::Arc;
 
-#[derive(Copy, Clone)]
+//Index of a material in Scene.materials, objects sharing an id share the material
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialId(pub(crate) u32);
+
+impl MaterialId{
+        pub fn index(&self) -> usize{
+                return self.0 as usize;
+        }
+}
+
+#[derive(Clone)]
 pub enum Material {
         Emission(Emission),
         Diffuse(Diffuse),
         Metal(Metal),
         Glass(Glass),
         Portal(Portal),
+        Principled(Principled),
+        Subsurface(Subsurface),
+        NormalMapped(NormalMapped),
+        //Material defined outside of this module
+        Custom(Arc<dyn Bsdf>),
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Emission{
-        pub color:Vector3,
-        pub emission:Vector3,
+        pub color:Texture,
+        pub emission:Texture,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Diffuse{
-        pub color:Vector3,
+        pub color:Texture,
+        pub model:DiffuseModel,
+        //Phong highlight, only used by DiffuseModel::Legacy
         pub specular_color:Vector3,
         pub specular_exp:f64,
         pub shininess:f64
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DiffuseModel{
+        //Lambert plus the Phong highlight on direct and indirect light, adds energy
+        Legacy,
+        Lambert,
+        //Rough diffuse of Oren and Nayar, sigma is the slope deviation in radians
+        OrenNayar{sigma:f64},
+}
+
+//GGX conductor, color tints the reflection on top of the fresnel term
+#[derive(Clone)]
 pub struct Metal{
-        pub color:Vector3,
-        pub fuzz:f64,
+        pub color:Texture,
+        pub roughness:Texture,
+        //0 is isotropic, towards 1 the highlight stretches across the tangent of the hit
+        pub anisotropy:f64,
+        //Turns of the tangent around the normal, a texture gives patterns like radial brushing
+        pub rotation:Texture,
+        //Complex IOR, None reflects everything at every angle like the old fuzzy metal
+        pub conductor:Option<Conductor>,
+        pub film:Option<ThinFilm>,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Glass{
-        pub color:Vector3,
+        pub color:Texture,
         pub refraction:f64,
+        //0 is perfectly smooth glass, higher values give frosted glass
+        pub roughness:Texture,
+        //Beer-Lambert coefficient per unit of distance travelled inside the object
+        pub absorption:Vector3,
+        //Wavelength dependent refraction used in spectral mode, refraction is used otherwise
+        pub dispersion:Option<Dispersion>,
+        pub film:Option<ThinFilm>,
 }
 
-#[derive(Copy, Clone)]
-pub struct Portal{
+//Interference coating like soap bubbles or oil slicks, changes the fresnel term of Glass and Metal
+#[derive(Clone)]
+pub struct ThinFilm{
+        //Thickness in nanometers, the visible colors come from about 100 to 1000
+        pub thickness:Texture,
+        pub ior:f64,
+}
+
+//Random walk inside a closed shape. Rays enter and leave through a diffuse boundary and scatter
+//in the volume in between. Each scatter event counts against volume_max_depth only, entering and
+//leaving are surface bounces like any other.
+#[derive(Clone)]
+pub struct Subsurface{
+        //Surface color given to the denoiser
         pub color:Vector3,
+        //Coefficients per unit of distance travelled inside the object
+        pub scattering:Vector3,
+        pub absorption:Vector3,
+        //Henyey-Greenstein mean cosine, 0 scatters the same in every direction
+        pub anisotropy:f64,
+        //Scatter once under the entry point and leave at the next hit, faster but loses the soft glow
+        pub single_scatter:bool,
+}
+
+//Any material shaded with the normal given by a normal or bump map
+#[derive(Clone)]
+pub struct NormalMapped{
+        pub material:Box<Material>,
+        pub map:NormalMap,
+}
+
+#[derive(Clone)]
+pub struct Portal{
+        pub color:Texture,
         pub position:Vector3,
         pub target:Vector3,
 }
 
+//Disney style uber material, a diffuse base with sheen, a GGX specular layer, a dielectric
+//transmission lobe and a clearcoat on top. Every parameter goes from 0 to 1 except ior.
+#[derive(Clone)]
+pub struct Principled{
+        pub base_color:Texture,
+        pub metallic:Texture,
+        pub roughness:Texture,
+        //Dielectric reflectance, 0.5 is the usual 4% at normal incidence
+        pub specular:f64,
+        pub sheen:f64,
+        //Blend of the sheen from white to the hue of the base color
+        pub sheen_tint:f64,
+        pub clearcoat:f64,
+        pub clearcoat_roughness:f64,
+        pub transmission:f64,
+        pub ior:f64,
+        pub emission:Texture,
+}
+
 impl Material{
-        pub fn attenuation(&self) -> Vector3{
+        pub fn attenuation(&self, hit_data:&HitData) -> Vector3{
                 match *self{
                         Material::Emission(ref m) => {
-                                return m.attenuation(); //Vector3::new(0.0, 0.0, 0.0);
+                                return m.attenuation(hit_data); //Vector3::new(0.0, 0.0, 0.0);
                         },
                         Material::Diffuse(ref m) => {
-                                return m.attenuation();
+                                return m.attenuation(hit_data);
                         },
                         Material::Metal(ref m) => {
-                                return m.attenuation();
+                                return m.attenuation(hit_data);
                         },
                         Material::Glass(ref m) => {
-                                return m.attenuation();
+                                return m.attenuation(hit_data);
                         },
                         Material::Portal(ref m) => {
-                                return m.attenuation();
+                                return m.attenuation(hit_data);
+                        },
+                        Material::Principled(ref m) => {
+                                return m.attenuation(hit_data);
+                        },
+                        Material::Subsurface(ref m) => {
+                                return m.attenuation(hit_data);
+                        },
+                        Material::NormalMapped(ref m) => {
+                                return m.material.attenuation(hit_data);
+                        },
+                        //Custom materials include their color in the sample weight
+                        Material::Custom(_) => {
+                                return Vector3::new(1.0, 1.0, 1.0);
+                        },
+                }
+        }
+
+        //Surface color for the denoiser, differs from attenuation when the color is part of the scatter weight
+        pub fn albedo(&self, hit_data:&HitData) -> Vector3{
+                match *self{
+                        Material::Principled(ref m) => {
+                                return m.base_color.evaluate(hit_data);
+                        },
+                        Material::Subsurface(ref m) => {
+                                return m.color;
+                        },
+                        Material::NormalMapped(ref m) => {
+                                return m.material.albedo(hit_data);
+                        },
+                        Material::Custom(ref m) => {
+                                return m.albedo(hit_data);
+                        },
+                        _ => {
+                                return self.attenuation(hit_data);
                         },
                 }
         }
@@ -87,62 +220,269 @@ impl Material{
                         Material::Portal(ref m) => {
                                 return Vector3::new(0.0, 0.0, 0.0);
                         },
+                        Material::Principled(ref m) => {
+                                return Vector3::new(0.0, 0.0, 0.0);
+                        },
+                        Material::Subsurface(ref m) => {
+                                return Vector3::new(0.0, 0.0, 0.0);
+                        },
+                        Material::NormalMapped(ref m) => {
+                                return m.material.specular(light_dir, norm, vision_dir);
+                        },
+                        Material::Custom(ref m) => {
+                                return Vector3::new(0.0, 0.0, 0.0);
+                        },
                 }
         }
 
-        pub fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, out:&mut Ray) -> bool{
+        //weight is left untouched by materials whose whole reflectance is the attenuation
+        pub fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, out:&mut Ray, weight:&mut Vector3, sampler:&mut dyn Sampler) -> bool{
                 match *self{
                         Material::Emission(ref m) => {
-                                return m.scatter(dir_in, hit_data, out);
+                                return m.scatter(dir_in, hit_data, out, weight, sampler);
                         },
                         Material::Diffuse(ref m) => {
-                                return m.scatter(dir_in, hit_data, out);
+                                return m.scatter(dir_in, hit_data, out, weight, sampler);
                         },
                         Material::Metal(ref m) => {
-                                return m.scatter(dir_in, hit_data, out);
+                                return m.scatter(dir_in, hit_data, out, weight, sampler);
                         },
                         Material::Glass(ref m) => {
-                                return m.scatter(dir_in, hit_data, out);
+                                return m.scatter(dir_in, hit_data, out, weight, sampler);
                         },
                         Material::Portal(ref m) => {
-                                return m.scatter(dir_in, hit_data, out);
+                                return m.scatter(dir_in, hit_data, out, weight, sampler);
+                        },
+                        Material::Principled(ref m) => {
+                                return m.scatter(dir_in, hit_data, out, weight, sampler);
+                        },
+                        Material::Subsurface(ref m) => {
+                                return m.scatter(dir_in, hit_data, out, weight, sampler);
+                        },
+                        Material::NormalMapped(ref m) => {
+                                return m.material.scatter(dir_in, hit_data, out, weight, sampler);
+                        },
+                        Material::Custom(ref m) => {
+                                let sample = match m.sample(dir_in, hit_data, &mut out.wavelengths, sampler){
+                                        Some(sample) => sample,
+                                        None => return false,
+                                };
+                                out.direction = sample.direction;
+                                if let Some(origin) = sample.origin{
+                                        out.origin = origin;
+                                }
+                                *weight = weight.mult(&sample.weight);
+                                return true;
+                        },
+                }
+        }
+}
+
+impl Material{
+        //Kind of bounce a ray scattered to out took
+        fn lobe(&self, dir_in:&Vector3, hit_data:&HitData, out:&Ray) -> BsdfLobe{
+                match *self{
+                        Material::Emission(_) | Material::Diffuse(_) => BsdfLobe::Diffuse,
+                        Material::Portal(_) => BsdfLobe::Portal,
+                        Material::Subsurface(_) if (out.origin - hit_data.point).norm() > 0.0 => BsdfLobe::Volume,
+                        _ => reflection_or_transmission(dir_in, hit_data, &out.direction),
+                }
+        }
+}
+
+impl Bsdf for Material{
+        fn flags(&self, hit_data:&HitData) -> BsdfFlags{
+                match *self{
+                        Material::Emission(_) => BsdfFlags{emissive:true, ..BsdfFlags::default()},
+                        Material::Diffuse(_) => BsdfFlags::default(),
+                        Material::Metal(ref m) => BsdfFlags{delta:m.distribution(hit_data).is_smooth(), ..BsdfFlags::default()},
+                        Material::Glass(ref m) => BsdfFlags{delta:m.roughness.evaluate_scalar(hit_data) <= 0.0, transmissive:true, ..BsdfFlags::default()},
+                        Material::Portal(_) => BsdfFlags{delta:true, transmissive:true, ..BsdfFlags::default()},
+                        Material::Subsurface(_) => BsdfFlags{transmissive:true, ..BsdfFlags::default()},
+                        Material::NormalMapped(ref m) => m.material.flags(hit_data),
+                        Material::Principled(ref m) => BsdfFlags{emissive:m.emission.average().norm() > 0.0, transmissive:m.transmission > 0.0, ..BsdfFlags::default()},
+                        Material::Custom(ref m) => m.flags(hit_data),
+                }
+        }
+
+        fn eval(&self, dir_in:&Vector3, dir_out:&Vector3, hit_data:&HitData, wavelengths:&Option<Wavelengths>) -> Vector3{
+                let value = match *self{
+                        Material::Diffuse(ref m) if m.model != DiffuseModel::Legacy => {
+                                m.eval(dir_in, dir_out, hit_data) * self.attenuation(hit_data)
+                        },
+                        //Old shading, the cosine of either side plus the Phong highlight. Lights carry the PI
+                        //the Lambert 1 / PI takes out, it is taken out here too so the old look is kept.
+                        Material::Emission(_) | Material::Diffuse(_) => {
+                                let cos = hit_data.norm.dot(dir_out).abs();
+                                (1.0 / std::f64::consts::PI) * (cos * self.attenuation(hit_data) + self.specular(dir_out, &effective_normal(hit_data), dir_in))
+                        },
+                        Material::Metal(ref m) => return m.eval(dir_in, dir_out, hit_data, wavelengths).mult(&spectrum::carried(&m.attenuation(hit_data), wavelengths)),
+                        Material::Principled(ref m) => m.eval(dir_in, dir_out, hit_data),
+                        Material::Subsurface(ref m) => return m.eval(dir_in, dir_out, hit_data, wavelengths),
+                        Material::NormalMapped(ref m) => return m.material.eval(dir_in, dir_out, hit_data, wavelengths),
+                        Material::Glass(ref m) => return m.eval(dir_in, dir_out, hit_data, wavelengths).mult(&spectrum::carried(&m.attenuation(hit_data), wavelengths)),
+                        Material::Portal(_) => Vector3::new(0.0, 0.0, 0.0),
+                        Material::Custom(ref m) => return m.eval(dir_in, dir_out, hit_data, wavelengths),
+                };
+                return spectrum::carried(&value, wavelengths);
+        }
+
+        fn pdf(&self, dir_in:&Vector3, dir_out:&Vector3, hit_data:&HitData, wavelengths:&Option<Wavelengths>) -> f64{
+                match *self{
+                        Material::Diffuse(ref m) if m.model != DiffuseModel::Legacy => m.pdf(dir_in, dir_out, hit_data),
+                        Material::Metal(ref m) => m.pdf(dir_in, dir_out, hit_data),
+                        Material::Glass(ref m) => m.pdf(dir_in, dir_out, hit_data, wavelengths),
+                        Material::Principled(ref m) => m.pdf(dir_in, dir_out, hit_data),
+                        Material::Subsurface(ref m) => m.pdf(dir_in, dir_out, hit_data, wavelengths),
+                        Material::NormalMapped(ref m) => m.material.pdf(dir_in, dir_out, hit_data, wavelengths),
+                        Material::Custom(ref m) => m.pdf(dir_in, dir_out, hit_data, wavelengths),
+                        //Delta lobes and the legacy sampler have no usable density
+                        _ => 0.0,
+                }
+        }
+
+        fn sample(&self, dir_in:&Vector3, hit_data:&HitData, wavelengths:&mut Option<Wavelengths>, sampler:&mut dyn Sampler) -> Option<BsdfSample>{
+                match *self{
+                        Material::NormalMapped(ref m) => return m.material.sample(dir_in, hit_data, wavelengths, sampler),
+                        Material::Custom(ref m) => return m.sample(dir_in, hit_data, wavelengths, sampler),
+                        _ => {},
+                }
+
+                let mut out = Ray{
+                        origin:hit_data.point,
+                        direction:Vector3::new(0.0, 0.0, 0.0),
+                        wavelengths:*wavelengths,
+                };
+                let mut weight = Vector3::new(1.0, 1.0, 1.0);
+                let (lobe, evaluated) = match *self{
+                        //Which principled lobe was picked can't be told from the direction
+                        Material::Principled(ref m) => m.scatter_lobe(dir_in, hit_data, &mut out, &mut weight, sampler),
+                        _ if self.scatter(dir_in, hit_data, &mut out, &mut weight, sampler) => Some((self.lobe(dir_in, hit_data, &out), true)),
+                        _ => None,
+                }?;
+                *wavelengths = out.wavelengths;
+
+                //The legacy highlight is added on top of the bounced light before the color
+                let specular = self.specular(&out.direction, &effective_normal(hit_data), dir_in);
+                let color = self.attenuation(hit_data).mult(&(Vector3::new(1.0, 1.0, 1.0) + specular));
+                let flags = self.flags(hit_data);
+
+                return Some(BsdfSample{
+                        direction:out.direction,
+                        weight:weight.mult(&spectrum::carried(&color, wavelengths)),
+                        pdf:if flags.delta || !evaluated {0.0} else {self.pdf(dir_in, &out.direction, hit_data, wavelengths)},
+                        delta:flags.delta,
+                        origin:if (out.origin - hit_data.point).norm() > 0.0 {Some(out.origin)} else {None},
+                        lobe,
+                });
+        }
+
+        fn perturb(&self, hit_data:&mut HitData){
+                match *self{
+                        Material::NormalMapped(ref m) => {
+                                m.material.perturb(hit_data);
+                                m.map.apply(hit_data);
                         },
+                        Material::Custom(ref m) => m.perturb(hit_data),
+                        _ => {},
+                }
+        }
+
+        fn emitted(&self, hit_data:&HitData, wavelengths:&Option<Wavelengths>) -> Vector3{
+                let value = match *self{
+                        Material::NormalMapped(ref m) => return m.material.emitted(hit_data, wavelengths),
+                        Material::Emission(ref m) => m.emitted(hit_data),
+                        Material::Principled(ref m) => m.emitted(hit_data),
+                        Material::Custom(ref m) => return m.emitted(hit_data, wavelengths),
+                        _ => return Vector3::new(0.0, 0.0, 0.0),
+                };
+                return spectrum::carried(&value, wavelengths);
+        }
+
+        fn is_emitter(&self) -> bool{
+                match *self{
+                        Material::Emission(_) => true,
+                        Material::Principled(ref m) => m.emission.average().norm() > 0.0,
+                        Material::NormalMapped(ref m) => m.material.is_emitter(),
+                        Material::Custom(ref m) => m.is_emitter(),
+                        _ => false,
+                }
+        }
+
+        fn transmittance(&self, hit_data:&HitData, wavelengths:&Option<Wavelengths>) -> Vector3{
+                match *self{
+                        Material::Glass(ref m) if hit_data.inside => spectrum::carried(&m.transmittance(hit_data.distance), wavelengths),
+                        Material::NormalMapped(ref m) => m.material.transmittance(hit_data, wavelengths),
+                        Material::Custom(ref m) => m.transmittance(hit_data, wavelengths),
+                        _ => Vector3::new(1.0, 1.0, 1.0),
                 }
         }
+
+        fn albedo(&self, hit_data:&HitData) -> Vector3{
+                return Material::albedo(self, hit_data);
+        }
 }
 
 pub trait Scatterable{
-        fn attenuation(&self) -> Vector3;
-        fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, ray:&mut Ray) -> bool;
+        fn attenuation(&self, hit_data:&HitData) -> Vector3;
+        //Chooses the outgoing ray, weight is multiplied by the sample throughput besides the attenuation.
+        //out starts with the wavelengths of the incoming ray and weight is in the same color space.
+        fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, ray:&mut Ray, weight:&mut Vector3, sampler:&mut dyn Sampler) -> bool;
 }
 
 impl Emission{
         pub fn create(color:Vector3, emission: Vector3) -> Material{
+                return Emission::create_textured(Texture::Solid(color), Texture::Solid(emission));
+        }
+
+        pub fn create_textured(color:Texture, emission: Texture) -> Material{
                 Material::Emission(Emission{
                         color,
                         emission,
                 })
         }
 
+        pub fn emitted(&self, hit_data:&HitData) -> Vector3{
+                return self.emission.evaluate(hit_data);
+        }
+
         //pub fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, out:&mut Ray) -> bool{
         //        return scatter_diffuse(dir_in, hit_data, &mut out.direction);
         //}
 }
 
 impl Scatterable for Emission{
-        fn attenuation(&self) -> Vector3{
-                return self.color;
+        fn attenuation(&self, hit_data:&HitData) -> Vector3{
+                return self.color.evaluate(hit_data);
         }
 
-        fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, out:&mut Ray) -> bool{
-                return scatter_diffuse(dir_in, hit_data, &mut out.direction);
+        fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, out:&mut Ray, weight:&mut Vector3, sampler:&mut dyn Sampler) -> bool{
+                return scatter_diffuse(dir_in, hit_data, &mut out.direction, sampler);
         }
 }
 
 impl Diffuse{
         pub const fn create(color:Vector3) -> Material{
+                return Diffuse::create_textured(Texture::Solid(color));
+        }
+
+        pub const fn create_textured(color:Texture) -> Material{
+                return Diffuse::create_model(color, DiffuseModel::Lambert);
+        }
+
+        //Lambert with the old Phong highlight
+        pub const fn create_legacy(color:Vector3) -> Material{
+                return Diffuse::create_model(Texture::Solid(color), DiffuseModel::Legacy);
+        }
+
+        pub const fn create_oren_nayar(color:Vector3, sigma:f64) -> Material{
+                return Diffuse::create_model(Texture::Solid(color), DiffuseModel::OrenNayar{sigma});
+        }
+
+        pub const fn create_model(color:Texture, model:DiffuseModel) -> Material{
                 Material::Diffuse(Diffuse{
                         color,
+                        model,
                         specular_color: Vector3::new(0.5, 0.5, 0.5),
                         specular_exp: 100.0,
                         shininess: 0.1
@@ -150,7 +490,9 @@ impl Diffuse{
         }
 
         pub fn specular(&self, light_dir:&Vector3, norm:&Vector3, vision_dir:&Vector3) -> Vector3{
-                
+                if self.model != DiffuseModel::Legacy{
+                        return Vector3::new(0.0, 0.0, 0.0);
+                }
 
                 let vision_reflected = vision_dir.reflect(norm);
                 let mut specular_attenuation = light_dir.dot(&vision_reflected);
@@ -163,76 +505,389 @@ impl Diffuse{
                 return Vector3::new(0.0, 0.0, 0.0);
         }
 
-        //pub fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, out:&mut Ray) -> bool{
-        //        return scatter_diffuse(dir_in, hit_data, &mut out.direction);
-        //}
-        
+        //Reflected radiance factor (brdf * cos) for light arriving from dir_light, without the color
+        pub fn eval(&self, dir_in:&Vector3, dir_light:&Vector3, hit_data:&HitData) -> f64{
+                let frame = Frame::from_normal(&effective_normal(hit_data));
+                let wo = frame.to_local(&-*dir_in);
+                let wi = frame.to_local(dir_light);
+                if wi.z <= 0.0 {return 0.0;}
+
+                return wi.z / std::f64::consts::PI * self.roughness_factor(&wo, &wi);
+        }
+
+        //Cosine weighted sampling for every model
+        pub fn pdf(&self, dir_in:&Vector3, dir_light:&Vector3, hit_data:&HitData) -> f64{
+                return sampling::cosine_hemisphere_pdf(effective_normal(hit_data).dot(dir_light));
+        }
+
+        //Oren-Nayar brdf relative to Lambert, 1 for the other models
+        fn roughness_factor(&self, wo:&Vector3, wi:&Vector3) -> f64{
+                let sigma = match self.model{
+                        DiffuseModel::OrenNayar{sigma} => sigma,
+                        _ => return 1.0,
+                };
+
+                let sigma2 = sigma * sigma;
+                let a = 1.0 - 0.5 * sigma2 / (sigma2 + 0.33);
+                let b = 0.45 * sigma2 / (sigma2 + 0.09);
+
+                let sin_o = (1.0 - wo.z * wo.z).max(0.0).sqrt();
+                let sin_i = (1.0 - wi.z * wi.z).max(0.0).sqrt();
+
+                //cos(phi_i - phi_o) from the projections on the tangent plane
+                let mut cos_phi = 0.0;
+                if sin_o > 1e-4 && sin_i > 1e-4{
+                        cos_phi = ((wo.x * wi.x + wo.y * wi.y) / (sin_o * sin_i)).max(0.0);
+                }
+
+                //sin(alpha) * tan(beta), alpha the largest angle to the normal and beta the smallest
+                let (sin_alpha, tan_beta) = if wi.z.abs() > wo.z.abs(){
+                        (sin_o, sin_i / wi.z.abs())
+                }else{
+                        (sin_i, sin_o / wo.z.abs().max(1e-8))
+                };
+
+                return a + b * cos_phi * sin_alpha * tan_beta;
+        }
 }
 
 impl Scatterable for Diffuse{
-        fn attenuation(&self) -> Vector3{
-                return self.color;
+        fn attenuation(&self, hit_data:&HitData) -> Vector3{
+                return self.color.evaluate(hit_data);
         }
 
-        fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, out:&mut Ray) -> bool{
-                return scatter_diffuse(dir_in, hit_data, &mut out.direction);
+        fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, out:&mut Ray, weight:&mut Vector3, sampler:&mut dyn Sampler) -> bool{
+                if self.model == DiffuseModel::Legacy{
+                        return scatter_diffuse(dir_in, hit_data, &mut out.direction, sampler);
+                }
+
+                //brdf * cos / pdf leaves the color, times the Oren-Nayar factor
+                let frame = Frame::from_normal(&effective_normal(hit_data));
+                let wo = frame.to_local(&-*dir_in);
+                let wi = random_cosine_direction(sampler);
+
+                *weight = self.roughness_factor(&wo, &wi) * *weight;
+                out.direction = frame.to_world(&wi).normalize();
+                return true;
         }
 }
 
 pub static BASE_MATERIAL:Material = Diffuse::create(Vector3::new(0.0, 0.0, 0.0));
 
 impl Metal{
+        //Fuzz maps directly to roughness, both go from mirror at 0 to very rough at 1
         pub fn create(color:Vector3, fuzz:f64) -> Material{
+                return Metal::create_textured(Texture::Solid(color), Texture::from(fuzz));
+        }
+
+        pub fn create_textured(color:Texture, roughness:Texture) -> Material{
                 Material::Metal(Metal{
                         color,
-                        fuzz,
+                        roughness,
+                        anisotropy:0.0,
+                        rotation:Texture::from(0.0),
+                        conductor:None,
+                        film:None,
                 })
         }
 
-        //pub fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, out:&mut Ray) -> bool{
-        //        return scatter_metal(dir_in, hit_data, self.fuzz, &mut out.direction);
-        //}
+        pub fn create_conductor(conductor:Conductor, roughness:f64) -> Material{
+                Material::Metal(Metal{
+                        color:Texture::from(1.0),
+                        roughness:Texture::from(roughness),
+                        anisotropy:0.0,
+                        rotation:Texture::from(0.0),
+                        conductor:Some(conductor),
+                        film:None,
+                })
+        }
+
+        //Brushed conductor, see Metal.anisotropy and Metal.rotation
+        pub fn create_anisotropic(conductor:Conductor, roughness:f64, anisotropy:f64, rotation:f64) -> Material{
+                Material::Metal(Metal{
+                        color:Texture::from(1.0),
+                        roughness:Texture::from(roughness),
+                        anisotropy,
+                        rotation:Texture::from(rotation),
+                        conductor:Some(conductor),
+                        film:None,
+                })
+        }
+
+        //Conductor under an interference coating, like anodized or heat tinted metal
+        pub fn create_thin_film(conductor:Conductor, roughness:f64, film:ThinFilm) -> Material{
+                Material::Metal(Metal{
+                        color:Texture::from(1.0),
+                        roughness:Texture::from(roughness),
+                        anisotropy:0.0,
+                        rotation:Texture::from(0.0),
+                        conductor:Some(conductor),
+                        film:Some(film),
+                })
+        }
 
         pub fn set_fuzz(&mut self, fuzz:f64){
-                self.fuzz = fuzz;
+                self.set_roughness(fuzz);
+        }
+
+        pub fn set_roughness(&mut self, roughness:f64){
+                self.roughness = Texture::from(roughness);
+        }
+
+        pub fn distribution(&self, hit_data:&HitData) -> Ggx{
+                return Ggx::from_roughness_anisotropic(self.roughness.evaluate_scalar(hit_data), self.anisotropy);
+        }
+
+        //Shading frame with the tangent the distribution is stretched along
+        pub fn frame(&self, hit_data:&HitData) -> Frame{
+                let frame = shading_frame(hit_data);
+                if self.anisotropy == 0.0{
+                        return frame;
+                }
+                return frame.rotated(2.0 * std::f64::consts::PI * self.rotation.evaluate_scalar(hit_data));
+        }
+
+        //Fresnel term in the color space of the ray
+        fn fresnel(&self, hit_data:&HitData, cos:f64, wavelengths:&Option<Wavelengths>) -> Vector3{
+                if let Some(ref film) = self.film{
+                        let conductor = self.conductor;
+                        //Without a conductor the base reflects almost everything
+                        let substrate = |lambda:f64| match conductor{
+                                Some(c) => c.ior_at(lambda),
+                                None => Complex::new(1.0, 1e3),
+                        };
+                        return film.reflectance(hit_data, cos, 1.0, substrate, wavelengths);
+                }
+
+                let fresnel = match self.conductor{
+                        Some(ref c) => c.fresnel(cos),
+                        None => Vector3::new(1.0, 1.0, 1.0),
+                };
+                return spectrum::carried(&fresnel, wavelengths);
+        }
+
+        //Reflected radiance factor (brdf * cos) for light arriving from dir_light, without the color
+        pub fn eval(&self, dir_in:&Vector3, dir_light:&Vector3, hit_data:&HitData, wavelengths:&Option<Wavelengths>) -> Vector3{
+                let frame = self.frame(hit_data);
+                let wo = frame.to_local(&-*dir_in);
+                let wi = frame.to_local(dir_light);
+                if wo.z <= 0.0 || wi.z <= 0.0 {return Vector3::new(0.0, 0.0, 0.0);}
+
+                let ggx = self.distribution(hit_data);
+                let m = (wo + wi).normalize();
+                let f = self.fresnel(hit_data, wo.dot(&m), wavelengths);
+                return (ggx.d(&m) * ggx.g2(&wo, &wi) / (4.0 * wo.z)) * f;
+        }
+
+        //Solid angle density of scatter choosing dir_light
+        pub fn pdf(&self, dir_in:&Vector3, dir_light:&Vector3, hit_data:&HitData) -> f64{
+                let frame = self.frame(hit_data);
+                let wo = frame.to_local(&-*dir_in);
+                let wi = frame.to_local(dir_light);
+                if wo.z <= 0.0 || wi.z <= 0.0 {return 0.0;}
+
+                let ggx = self.distribution(hit_data);
+                let m = (wo + wi).normalize();
+                return ggx.pdf_visible(&wo, &m) / (4.0 * wo.dot(&m));
         }
 }
 
 impl Scatterable for Metal{
-        fn attenuation(&self) -> Vector3{
-                return self.color;
+        fn attenuation(&self, hit_data:&HitData) -> Vector3{
+                return self.color.evaluate(hit_data);
         }
 
-        fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, out:&mut Ray) -> bool{
-                return scatter_metal(dir_in, hit_data, self.fuzz, &mut out.direction);
+        fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, out:&mut Ray, weight:&mut Vector3, sampler:&mut dyn Sampler) -> bool{
+                let ggx = self.distribution(hit_data);
+                let wavelengths = out.wavelengths;
+                return scatter_microfacet_reflection(dir_in, &self.frame(hit_data), &ggx, &mut out.direction, weight, |cos| self.fresnel(hit_data, cos, &wavelengths), sampler);
         }
 }
 
 impl Glass{
         pub fn create(color:Vector3, refraction:f64) -> Material{
+                return Glass::create_textured(Texture::Solid(color), refraction);
+        }
+
+        pub fn create_textured(color:Texture, refraction:f64) -> Material{
+                return Glass::create_rough(color, refraction, Texture::from(0.0));
+        }
+
+        pub fn create_rough(color:Texture, refraction:f64, roughness:Texture) -> Material{
                 Material::Glass(Glass{
                         color,
                         refraction,
+                        roughness,
+                        absorption:Vector3::new(0.0, 0.0, 0.0),
+                        dispersion:None,
+                        film:None,
+                })
+        }
+
+        //Clear surface tinted by its volume, a ray travelling distance inside keeps color of its energy
+        pub fn create_absorbing(color:Vector3, distance:f64, refraction:f64, roughness:f64) -> Material{
+                Material::Glass(Glass{
+                        color:Texture::from(1.0),
+                        refraction,
+                        roughness:Texture::from(roughness),
+                        absorption:Glass::absorption_from_color(&color, distance),
+                        dispersion:None,
+                        film:None,
+                })
+        }
+
+        //Refraction is the index at about 587nm, used when rendering in RGB
+        pub fn create_dispersive(color:Vector3, dispersion:Dispersion, roughness:f64) -> Material{
+                Material::Glass(Glass{
+                        color:Texture::from(color),
+                        refraction:dispersion.ior(587.6),
+                        roughness:Texture::from(roughness),
+                        absorption:Vector3::new(0.0, 0.0, 0.0),
+                        dispersion:Some(dispersion),
+                        film:None,
+                })
+        }
+
+        //Coated clear glass, a refraction of 1 gives a soap bubble
+        pub fn create_thin_film(refraction:f64, roughness:f64, film:ThinFilm) -> Material{
+                Material::Glass(Glass{
+                        color:Texture::from(1.0),
+                        refraction,
+                        roughness:Texture::from(roughness),
+                        absorption:Vector3::new(0.0, 0.0, 0.0),
+                        dispersion:None,
+                        film:Some(film),
                 })
         }
 
+        pub fn absorption_from_color(color:&Vector3, distance:f64) -> Vector3{
+                let coefficient = |c:f64| -c.max(1e-6).ln() / distance;
+                return Vector3::new(coefficient(color.x), coefficient(color.y), coefficient(color.z));
+        }
+
+        pub fn transmittance(&self, distance:f64) -> Vector3{
+                return Vector3::new((-self.absorption.x * distance).exp(), (-self.absorption.y * distance).exp(), (-self.absorption.z * distance).exp());
+        }
+
+        //Index at the hero wavelength for dispersive glass, with the weight that keeps only the hero
+        //wavelength as scatter does
+        fn dispersed(&self, wavelengths:&Option<Wavelengths>) -> (f64, Option<Wavelengths>, Vector3){
+                let mut wavelengths = *wavelengths;
+                if let (Some(dispersion), Some(w)) = (self.dispersion, wavelengths.as_mut()){
+                        let refraction = dispersion.ior(w.hero());
+                        let weight = w.terminate_secondary();
+                        return (refraction, wavelengths, weight);
+                }
+                return (self.refraction, wavelengths, Vector3::new(1.0, 1.0, 1.0));
+        }
+
+        //Radiance factor without the color and density of the rough lobes, smooth glass has neither
+        fn eval_pdf(&self, dir_in:&Vector3, dir_light:&Vector3, hit_data:&HitData, refraction:f64, wavelengths:&Option<Wavelengths>) -> (Vector3, f64){
+                let roughness = self.roughness.evaluate_scalar(hit_data);
+                if roughness <= 0.0{
+                        return (Vector3::new(0.0, 0.0, 0.0), 0.0);
+                }
+
+                let frame = Frame::from_normal(&effective_normal(hit_data));
+                let wo = frame.to_local(&-*dir_in);
+                let wi = frame.to_local(dir_light);
+                let r = if hit_data.inside {refraction} else {1.0 / refraction};
+                let ggx = Ggx::from_roughness(roughness);
+
+                if let Some(ref film) = self.film{
+                        let (outside, substrate) = if hit_data.inside {(refraction, 1.0)} else {(1.0, refraction)};
+                        return eval_pdf_rough_glass(&wo, &wi, r, &ggx, |cos| film.reflectance(hit_data, cos, outside, |_| Complex::real(substrate), wavelengths));
+                }
+                return eval_pdf_rough_glass(&wo, &wi, r, &ggx, |cos| Vector3::new(1.0, 1.0, 1.0) * microfacet::fresnel_dielectric(cos, r));
+        }
+
+        //Radiance factor (bsdf * cos) for light arriving from dir_light, without the color
+        pub fn eval(&self, dir_in:&Vector3, dir_light:&Vector3, hit_data:&HitData, wavelengths:&Option<Wavelengths>) -> Vector3{
+                let (refraction, wavelengths, weight) = self.dispersed(wavelengths);
+                return self.eval_pdf(dir_in, dir_light, hit_data, refraction, &wavelengths).0.mult(&weight);
+        }
+
+        //Solid angle density of scatter choosing dir_light
+        pub fn pdf(&self, dir_in:&Vector3, dir_light:&Vector3, hit_data:&HitData, wavelengths:&Option<Wavelengths>) -> f64{
+                let (refraction, wavelengths, _) = self.dispersed(wavelengths);
+                return self.eval_pdf(dir_in, dir_light, hit_data, refraction, &wavelengths).1;
+        }
+
         //pub fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, out:&mut Ray) -> bool{
         //        return scatter_glass(dir_in, hit_data, self.refraction, &mut out.direction);
         //}
 }
 
 impl Scatterable for Glass{
-        fn attenuation(&self) -> Vector3{
-                return self.color;
+        fn attenuation(&self, hit_data:&HitData) -> Vector3{
+                return self.color.evaluate(hit_data);
         }
 
-        fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, out:&mut Ray) -> bool{
-                return scatter_glass(dir_in, hit_data, self.refraction, &mut out.direction);
+        fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, out:&mut Ray, weight:&mut Vector3, sampler:&mut dyn Sampler) -> bool{
+                let mut refraction = self.refraction;
+
+                //Each wavelength bends differently, only the hero one can follow the sampled direction
+                if let (Some(dispersion), Some(wavelengths)) = (self.dispersion, out.wavelengths.as_mut()){
+                        refraction = dispersion.ior(wavelengths.hero());
+                        *weight = weight.mult(&wavelengths.terminate_secondary());
+                }
+
+                let roughness = self.roughness.evaluate_scalar(hit_data);
+                if let Some(ref film) = self.film{
+                        //The film is on the outer side, from inside it sits between the glass and the air
+                        let (outside, substrate) = if hit_data.inside {(refraction, 1.0)} else {(1.0, refraction)};
+                        let wavelengths = out.wavelengths;
+                        let ggx = if roughness <= 0.0 {None} else {Some(Ggx::from_roughness(roughness))};
+                        return scatter_coated_glass(dir_in, hit_data, refraction, ggx.as_ref(), &mut out.direction, weight,
+                                |cos| film.reflectance(hit_data, cos, outside, |_| Complex::real(substrate), &wavelengths), sampler);
+                }
+                if roughness <= 0.0{
+                        return scatter_glass(dir_in, hit_data, refraction, &mut out.direction, sampler);
+                }
+                return scatter_rough_glass(dir_in, hit_data, refraction, &Ggx::from_roughness(roughness), &mut out.direction, weight, sampler);
+        }
+}
+
+impl ThinFilm{
+        pub fn new(thickness:Texture, ior:f64) -> ThinFilm{
+                ThinFilm{
+                        thickness,
+                        ior,
+                }
+        }
+
+        //Reflectance over a substrate given by its index at a wavelength, in the color space of the ray.
+        //RGB rays use the closed form integral per channel, with the substrate at the channel wavelength.
+        pub fn reflectance<F:Fn(f64) -> Complex>(&self, hit_data:&HitData, cos:f64, outside:f64, substrate:F, wavelengths:&Option<Wavelengths>) -> Vector3{
+                let thickness = self.thickness.evaluate_scalar(hit_data).max(0.0);
+                match wavelengths{
+                        Some(w) => {
+                                let at = |lambda:f64| microfacet::fresnel_thin_film(cos, outside, self.ior, thickness, substrate(lambda), lambda);
+                                Vector3::new(at(w.lambda.x), at(w.lambda.y), at(w.lambda.z))
+                        },
+                        None => {
+                                let at = |lambda:f64, channel:usize| microfacet::fresnel_thin_film_rgb(cos, outside, self.ior, thickness, substrate(lambda)).component(channel);
+                                Vector3::new(at(650.0, 0), at(550.0, 1), at(450.0, 2))
+                        },
+                }
+        }
+}
+
+impl NormalMapped{
+        pub fn create(material:Material, map:NormalMap) -> Material{
+                Material::NormalMapped(NormalMapped{
+                        material:Box::new(material),
+                        map,
+                })
         }
 }
 
 impl Portal{
         pub fn create(color:Vector3, position:Vector3, target:Vector3) -> Material{
+                return Portal::create_textured(Texture::Solid(color), position, target);
+        }
+
+        pub fn create_textured(color:Texture, position:Vector3, target:Vector3) -> Material{
                 Material::Portal(Portal{
                         color,
                         position,
@@ -258,11 +913,11 @@ impl Portal{
 }
 
 impl Scatterable for Portal{
-        fn attenuation(&self) -> Vector3{
-                return self.color;
+        fn attenuation(&self, hit_data:&HitData) -> Vector3{
+                return self.color.evaluate(hit_data);
         }
 
-        fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, out:&mut Ray) -> bool{
+        fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, out:&mut Ray, weight:&mut Vector3, sampler:&mut dyn Sampler) -> bool{
                 //Creating portal border
                 if dir_in.dot(&hit_data.norm).abs() < 0.1{
                         return false;
@@ -279,7 +934,162 @@ impl Scatterable for Portal{
         }
 }
 
-fn scatter_diffuse(dir_in:&Vector3, hit_data:&HitData, out:&mut Vector3) -> bool{
+impl Subsurface{
+        //Coefficients giving color as the albedo of a thick slab, radius is the mean free path of
+        //each channel (Chiang et al. 2016)
+        pub fn create(color:Vector3, radius:Vector3) -> Material{
+                let single_albedo = |a:f64| {
+                        let a = a.clamp(0.0, 0.999);
+                        1.0 - (4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt()).powi(2)
+                };
+                let extinction = Vector3::new(1.0 / radius.x, 1.0 / radius.y, 1.0 / radius.z);
+                let scattering = Vector3::new(single_albedo(color.x), single_albedo(color.y), single_albedo(color.z)).mult(&extinction);
+
+                Material::Subsurface(Subsurface{
+                        color,
+                        scattering,
+                        absorption:extinction - scattering,
+                        anisotropy:0.0,
+                        single_scatter:false,
+                })
+        }
+
+        pub fn create_single_scatter(color:Vector3, radius:Vector3) -> Material{
+                let mut material = Subsurface::create(color, radius);
+                if let Material::Subsurface(ref mut m) = material{
+                        m.single_scatter = true;
+                }
+                return material;
+        }
+
+        //Measured coefficients, anisotropy as in Subsurface.anisotropy
+        pub fn create_coefficients(scattering:Vector3, absorption:Vector3, anisotropy:f64) -> Material{
+                let extinction = scattering + absorption;
+                Material::Subsurface(Subsurface{
+                        color:Vector3::new(scattering.x / extinction.x, scattering.y / extinction.y, scattering.z / extinction.z),
+                        scattering,
+                        absorption,
+                        anisotropy,
+                        single_scatter:false,
+                })
+        }
+
+        //Scattering and extinction in the color space of the ray
+        fn coefficients(&self, wavelengths:&Option<Wavelengths>) -> (Vector3, Vector3){
+                let scattering = spectrum::carried(&self.scattering, wavelengths);
+                let extinction = scattering + spectrum::carried(&self.absorption, wavelengths);
+                return (scattering, extinction);
+        }
+
+        //Chance of the walk leaving at a hit from inside instead of scattering before it
+        fn exit_probability(&self, extinction:&Vector3, distance:f64) -> f64{
+                if self.single_scatter{
+                        return 1.0;
+                }
+                let transmittance = volume_transmittance(extinction, distance);
+                return (transmittance.x + transmittance.y + transmittance.z) / 3.0;
+        }
+
+        //Light arriving at a hit from inside leaves the object through the diffuse boundary, if the
+        //segment that ended there made it through the volume
+        pub fn eval(&self, dir_in:&Vector3, dir_light:&Vector3, hit_data:&HitData, wavelengths:&Option<Wavelengths>) -> Vector3{
+                let cos = hit_data.norm.dot(dir_light);
+                if !hit_data.inside || cos <= 0.0{
+                        return Vector3::new(0.0, 0.0, 0.0);
+                }
+
+                let (_, extinction) = self.coefficients(wavelengths);
+                return cos / std::f64::consts::PI * volume_transmittance(&extinction, hit_data.distance);
+        }
+
+        pub fn pdf(&self, dir_in:&Vector3, dir_light:&Vector3, hit_data:&HitData, wavelengths:&Option<Wavelengths>) -> f64{
+                let cos = hit_data.norm.dot(dir_light);
+                if !hit_data.inside{
+                        return sampling::cosine_hemisphere_pdf(-cos);
+                }
+
+                //Same coefficients as scatter, so the exit chance matches the walk that was sampled
+                let (_, extinction) = self.coefficients(wavelengths);
+                return self.exit_probability(&extinction, hit_data.distance) * sampling::cosine_hemisphere_pdf(cos);
+        }
+}
+
+impl Scatterable for Subsurface{
+        //The volume color is part of the walk weight
+        fn attenuation(&self, hit_data:&HitData) -> Vector3{
+                return Vector3::new(1.0, 1.0, 1.0);
+        }
+
+        fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, out:&mut Ray, weight:&mut Vector3, sampler:&mut dyn Sampler) -> bool{
+                let (scattering, extinction) = self.coefficients(&out.wavelengths);
+
+                if !hit_data.inside{
+                        //Enter through the diffuse boundary
+                        out.direction = Frame::from_normal(&-hit_data.norm).to_world(&random_cosine_direction(sampler)).normalize();
+                        if self.single_scatter{
+                                //The depth ignores the shape, thin parts may scatter outside of it
+                                let (t, pdf) = sample_free_flight(&extinction, sampler);
+                                if !t.is_finite(){
+                                        return false;
+                                }
+                                *weight = weight.mult(&((1.0 / pdf) * scattering.mult(&volume_transmittance(&extinction, t))));
+                                out.origin = hit_data.point + t * out.direction;
+                                out.direction = sample_henyey_greenstein(&out.direction, self.anisotropy, sampler);
+                        }
+                        return true;
+                }
+
+                //The segment that ended here started distance back along dir_in
+                let distance = hit_data.distance;
+                let transmittance = volume_transmittance(&extinction, distance);
+                if !self.single_scatter{
+                        let (t, pdf) = sample_free_flight(&extinction, sampler);
+                        if t < distance{
+                                *weight = weight.mult(&((1.0 / pdf) * scattering.mult(&volume_transmittance(&extinction, t))));
+                                out.origin = hit_data.point - (distance - t) * dir_in;
+                                out.direction = sample_henyey_greenstein(dir_in, self.anisotropy, sampler);
+                                return true;
+                        }
+                }
+
+                //Leave through the diffuse boundary
+                *weight = weight.mult(&((1.0 / self.exit_probability(&extinction, distance)) * transmittance));
+                out.direction = Frame::from_normal(&hit_data.norm).to_world(&random_cosine_direction(sampler)).normalize();
+                return true;
+        }
+}
+
+fn volume_transmittance(extinction:&Vector3, distance:f64) -> Vector3{
+        return Vector3::new((-extinction.x * distance).exp(), (-extinction.y * distance).exp(), (-extinction.z * distance).exp());
+}
+
+//Distance to the next scatter event, sampled on a random channel. The pdf is the average over the
+//channels so colored media stay unbiased.
+fn sample_free_flight(extinction:&Vector3, sampler:&mut dyn Sampler) -> (f64, f64){
+        let (u1, u2) = sampler.get_2d();
+        let channel = ((3.0 * u1) as usize).min(2);
+        let t = -(1.0 - u2).ln() / extinction.component(channel);
+
+        let density = extinction.mult(&volume_transmittance(extinction, t));
+        return (t, (density.x + density.y + density.z) / 3.0);
+}
+
+//Henyey-Greenstein phase function around the direction of travel, its weight is always 1
+fn sample_henyey_greenstein(direction:&Vector3, g:f64, sampler:&mut dyn Sampler) -> Vector3{
+        let (u, v) = sampler.get_2d();
+        let cos = if g.abs() < 1e-3{
+                1.0 - 2.0 * u
+        }else{
+                let s = (1.0 - g * g) / (1.0 + g - 2.0 * g * u);
+                (1.0 + g * g - s * s) / (2.0 * g)
+        };
+
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * v;
+        return Frame::from_normal(direction).to_world(&Vector3::new(sin * phi.cos(), sin * phi.sin(), cos)).normalize();
+}
+
+fn scatter_diffuse(dir_in:&Vector3, hit_data:&HitData, out:&mut Vector3, sampler:&mut dyn Sampler) -> bool{
         let effective_norm:Vector3;
 
         if hit_data.inside{
@@ -288,35 +1098,312 @@ fn scatter_diffuse(dir_in:&Vector3, hit_data:&HitData, out:&mut Vector3) -> bool
         }else{
                 effective_norm = hit_data.norm;
         }
-        
-        //*out = (random_in_hemisphere(&effective_norm)).normalize();
-        //*out = (effective_norm + random_in_unit_sphere()).normalize();
-        *out = (effective_norm + random_unit_vector()).normalize(); //Lambertian
+
+        *out = sampling::to_basis(&effective_norm, &random_cosine_direction(sampler)).normalize(); //Lambertian
         return true;
 }
 
-fn scatter_metal(dir_in:&Vector3, hit_data:&HitData, fuzz:f64, out:&mut Vector3) -> bool{
-        let effective_norm:Vector3;
+impl Principled{
+        pub fn new(base_color:Texture) -> Principled{
+                Principled{
+                        base_color,
+                        metallic:Texture::from(0.0),
+                        roughness:Texture::from(0.5),
+                        specular:0.5,
+                        sheen:0.0,
+                        sheen_tint:0.5,
+                        clearcoat:0.0,
+                        clearcoat_roughness:0.1,
+                        transmission:0.0,
+                        ior:1.5,
+                        emission:Texture::from(0.0),
+                }
+        }
+
+        pub fn create(base_color:Vector3, metallic:f64, roughness:f64) -> Material{
+                Material::Principled(Principled{
+                        metallic:Texture::from(metallic),
+                        roughness:Texture::from(roughness),
+                        ..Principled::new(Texture::from(base_color))
+                })
+        }
+
+        pub fn emitted(&self, hit_data:&HitData) -> Vector3{
+                return self.emission.evaluate(hit_data);
+        }
+
+        fn lobes(&self, hit_data:&HitData) -> PrincipledLobes{
+                let base = self.base_color.evaluate(hit_data);
+                let metallic = self.metallic.evaluate_scalar(hit_data).clamp(0.0, 1.0);
+                let dielectric = 1.0 - metallic;
+
+                let luminance = luminance(&base);
+                let tint = if luminance > 0.0 {1.0 / luminance * base} else {Vector3::new(1.0, 1.0, 1.0)};
+                let white = Vector3::new(1.0, 1.0, 1.0);
+                let specular_f0 = 0.08 * self.specular * white;
+
+                PrincipledLobes{
+                        base,
+                        diffuse:dielectric * (1.0 - self.transmission),
+                        sheen_color:self.sheen * ((1.0 - self.sheen_tint) * white + self.sheen_tint * tint),
+                        //The transmission lobe already reflects with the exact dielectric fresnel
+                        specular:1.0 - dielectric * self.transmission,
+                        f0:dielectric * specular_f0 + metallic * base,
+                        ggx:Ggx::from_roughness(self.roughness.evaluate_scalar(hit_data)),
+                        clearcoat:0.25 * self.clearcoat,
+                        clearcoat_ggx:Ggx::from_roughness(self.clearcoat_roughness),
+                        transmission:dielectric * self.transmission,
+                }
+        }
+
+        //Reflected radiance factor (brdf * cos) of every reflection lobe, wo and wi in the local frame
+        fn eval_local(lobes:&PrincipledLobes, wo:&Vector3, wi:&Vector3) -> Vector3{
+                let h = (*wo + *wi).normalize();
+                let cos_d = wi.dot(&h);
+                let fresnel_weight = schlick_weight(cos_d);
+
+                let diffuse = (lobes.diffuse * wi.z) * ((1.0 / std::f64::consts::PI) * lobes.base + fresnel_weight * lobes.sheen_color);
+
+                let specular_fresnel = lobes.f0 + fresnel_weight * (Vector3::new(1.0, 1.0, 1.0) - lobes.f0);
+                let specular = (lobes.specular * lobes.ggx.d(&h) * lobes.ggx.g2(wo, wi) / (4.0 * wo.z)) * specular_fresnel;
+
+                let clearcoat_fresnel = 0.04 + 0.96 * fresnel_weight;
+                let clearcoat = lobes.clearcoat * clearcoat_fresnel * lobes.clearcoat_ggx.d(&h) * lobes.clearcoat_ggx.g2(wo, wi) / (4.0 * wo.z);
+
+                return diffuse + specular + Vector3::new(clearcoat, clearcoat, clearcoat);
+        }
+
+        //Probability of picking each lobe, diffuse, specular, clearcoat and transmission
+        fn lobe_probabilities(lobes:&PrincipledLobes, cos:f64) -> [f64; 4]{
+                let schlick = schlick_weight(cos);
+                let specular_fresnel = lobes.f0 + schlick * (Vector3::new(1.0, 1.0, 1.0) - lobes.f0);
+
+                let mut p = [
+                        lobes.diffuse * luminance(&lobes.base).max(luminance(&lobes.sheen_color)),
+                        lobes.specular * luminance(&specular_fresnel),
+                        lobes.clearcoat * (0.04 + 0.96 * schlick),
+                        lobes.transmission,
+                ];
+                let sum:f64 = p.iter().sum();
+                if sum <= 0.0 {return [1.0, 0.0, 0.0, 0.0];}
+                for value in p.iter_mut(){
+                        *value /= sum;
+                }
+                return p;
+        }
+
+        //Density of choosing wi through any of the reflection lobes
+        fn pdf_local(lobes:&PrincipledLobes, p:&[f64; 4], wo:&Vector3, wi:&Vector3) -> f64{
+                let h = (*wo + *wi).normalize();
+                let reflection_jacobian = 1.0 / (4.0 * wo.dot(&h));
+                return p[0] * sampling::cosine_hemisphere_pdf(wi.z)
+                        + p[1] * lobes.ggx.pdf_visible(wo, &h) * reflection_jacobian
+                        + p[2] * lobes.clearcoat_ggx.pdf_visible(wo, &h) * reflection_jacobian;
+        }
+
+        //Radiance factor (bsdf * cos) and density of the transmission lobe alone, which reflects and
+        //refracts like rough glass. Smooth transmission is a delta lobe and has neither.
+        fn eval_pdf_transmission(&self, lobes:&PrincipledLobes, wo:&Vector3, wi:&Vector3, inside:bool) -> (Vector3, f64){
+                if lobes.ggx.is_smooth(){
+                        return (Vector3::new(0.0, 0.0, 0.0), 0.0);
+                }
+
+                let r = if inside {self.ior} else {1.0 / self.ior};
+                let (value, pdf) = eval_pdf_rough_glass(wo, wi, r, &lobes.ggx, |cos| Vector3::new(1.0, 1.0, 1.0) * microfacet::fresnel_dielectric(cos, r));
+                //Refracted light is tinted when it enters, as in scatter_transmission
+                if !inside && wi.z < 0.0{
+                        return (value.mult(&lobes.base), pdf);
+                }
+                return (value, pdf);
+        }
+
+        //Radiance factor (bsdf * cos) for light arriving from dir_light
+        pub fn eval(&self, dir_in:&Vector3, dir_light:&Vector3, hit_data:&HitData) -> Vector3{
+                let frame = Frame::from_normal(&effective_normal(hit_data));
+                let wo = frame.to_local(&-*dir_in);
+                let wi = frame.to_local(dir_light);
+                if wo.z <= 0.0 {return Vector3::new(0.0, 0.0, 0.0);}
+
+                let lobes = self.lobes(hit_data);
+                //Only the transmission lobe reaches the inside of an object
+                if hit_data.inside{
+                        return self.eval_pdf_transmission(&lobes, &wo, &wi, true).0;
+                }
+
+                let transmission = lobes.transmission * self.eval_pdf_transmission(&lobes, &wo, &wi, false).0;
+                if wi.z <= 0.0{
+                        return transmission;
+                }
+                return Principled::eval_local(&lobes, &wo, &wi) + transmission;
+        }
+
+        pub fn pdf(&self, dir_in:&Vector3, dir_light:&Vector3, hit_data:&HitData) -> f64{
+                let frame = Frame::from_normal(&effective_normal(hit_data));
+                let wo = frame.to_local(&-*dir_in);
+                let wi = frame.to_local(dir_light);
+                if wo.z <= 0.0 {return 0.0;}
+
+                let lobes = self.lobes(hit_data);
+                let transmission = self.eval_pdf_transmission(&lobes, &wo, &wi, hit_data.inside).1;
+                if hit_data.inside{
+                        return transmission;
+                }
+
+                let p = Principled::lobe_probabilities(&lobes, wo.z);
+                let reflection = if wi.z > 0.0 {Principled::pdf_local(&lobes, &p, &wo, &wi)} else {0.0};
+                return reflection + p[3] * transmission;
+        }
+
+        fn scatter_transmission(&self, dir_in:&Vector3, hit_data:&HitData, lobes:&PrincipledLobes, out:&mut Ray, weight:&mut Vector3, sampler:&mut dyn Sampler) -> bool{
+                let scattered = if lobes.ggx.is_smooth(){
+                        scatter_glass(dir_in, hit_data, self.ior, &mut out.direction, sampler)
+                }else{
+                        scatter_rough_glass(dir_in, hit_data, self.ior, &lobes.ggx, &mut out.direction, weight, sampler)
+                };
+
+                //Refracted light is tinted when it enters, once per crossing
+                if scattered && !hit_data.inside && out.direction.dot(&hit_data.norm) < 0.0{
+                        *weight = weight.mult(&spectrum::carried(&lobes.base, &out.wavelengths));
+                }
+                return scattered;
+        }
+
+        fn scatter_transmission_lobe(&self, dir_in:&Vector3, hit_data:&HitData, lobes:&PrincipledLobes, out:&mut Ray, weight:&mut Vector3, sampler:&mut dyn Sampler) -> Option<BsdfLobe>{
+                if !self.scatter_transmission(dir_in, hit_data, lobes, out, weight, sampler){
+                        return None;
+                }
+                return Some(reflection_or_transmission(dir_in, hit_data, &out.direction));
+        }
+
+        //Scatters like Scatterable::scatter and tells which lobe was sampled, and whether eval and pdf cover
+        //it. They leave out smooth transmission, which is a delta lobe.
+        fn scatter_lobe(&self, dir_in:&Vector3, hit_data:&HitData, out:&mut Ray, weight:&mut Vector3, sampler:&mut dyn Sampler) -> Option<(BsdfLobe, bool)>{
+                let lobes = self.lobes(hit_data);
+
+                //Only the transmission lobe reaches the inside of an object
+                let evaluated = !lobes.ggx.is_smooth();
+                if hit_data.inside{
+                        return self.scatter_transmission_lobe(dir_in, hit_data, &lobes, out, weight, sampler).map(|lobe| (lobe, evaluated));
+                }
+
+                let frame = Frame::from_normal(&hit_data.norm);
+                let wo = frame.to_local(&-*dir_in);
+                if wo.z <= 0.0{
+                        return None;
+                }
 
+                let p = Principled::lobe_probabilities(&lobes, wo.z);
+                let u = sampler.get_1d();
+
+                if u >= p[0] + p[1] + p[2]{
+                        *weight = (lobes.transmission / p[3]) * *weight;
+                        return self.scatter_transmission_lobe(dir_in, hit_data, &lobes, out, weight, sampler).map(|lobe| (lobe, evaluated));
+                }
+
+                let (wi, lobe) = if u < p[0]{
+                        (random_cosine_direction(sampler), BsdfLobe::Diffuse)
+                }else{
+                        let ggx = if u < p[0] + p[1] {&lobes.ggx} else {&lobes.clearcoat_ggx};
+                        let (u1, u2) = sampler.get_2d();
+                        let m = ggx.sample_visible(&wo, u1, u2);
+                        (microfacet::reflect(&wo, &m), BsdfLobe::Glossy)
+                };
+                if wi.z <= 0.0{
+                        return None;
+                }
+
+                let pdf = Principled::pdf_local(&lobes, &p, &wo, &wi);
+                if pdf <= 0.0{
+                        return None;
+                }
+
+                let f = Principled::eval_local(&lobes, &wo, &wi);
+                *weight = weight.mult(&spectrum::carried(&(1.0 / pdf * f), &out.wavelengths));
+                out.direction = frame.to_world(&wi).normalize();
+                return Some((lobe, true));
+        }
+}
+
+//Principled parameters evaluated at a hit point
+struct PrincipledLobes{
+        base:Vector3,
+        diffuse:f64,
+        sheen_color:Vector3,
+        specular:f64,
+        f0:Vector3,
+        ggx:Ggx,
+        clearcoat:f64,
+        clearcoat_ggx:Ggx,
+        transmission:f64,
+}
+
+impl Scatterable for Principled{
+        //The base color is part of the lobe weights
+        fn attenuation(&self, hit_data:&HitData) -> Vector3{
+                return Vector3::new(1.0, 1.0, 1.0);
+        }
+
+        fn scatter(&self, dir_in:&Vector3, hit_data:&HitData, out:&mut Ray, weight:&mut Vector3, sampler:&mut dyn Sampler) -> bool{
+                return self.scatter_lobe(dir_in, hit_data, out, weight, sampler).is_some();
+        }
+}
+
+fn luminance(color:&Vector3) -> f64{
+        return 0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z;
+}
+
+//(1 - cos)^5 of the Schlick fresnel approximation
+fn schlick_weight(cos:f64) -> f64{
+        let m = (1.0 - cos).clamp(0.0, 1.0);
+        return m * m * m * m * m;
+}
+
+//Cosine weighted direction around +z
+fn random_cosine_direction(sampler:&mut dyn Sampler) -> Vector3{
+        let (u1, u2) = sampler.get_2d();
+        return sampling::cosine_hemisphere(u1, u2).0;
+}
+
+//Transmission when direction carries on through the surface, a glossy reflection otherwise
+fn reflection_or_transmission(dir_in:&Vector3, hit_data:&HitData, direction:&Vector3) -> BsdfLobe{
+        if dir_in.dot(&hit_data.geometric_norm) * direction.dot(&hit_data.geometric_norm) > 0.0{
+                return BsdfLobe::Transmission;
+        }
+        return BsdfLobe::Glossy;
+}
+
+fn effective_normal(hit_data:&HitData) -> Vector3{
         if hit_data.inside{
-                //Inside of object
-                effective_norm = -hit_data.norm;
-        }else{
-                effective_norm = hit_data.norm;
+                return -hit_data.norm;
         }
+        return hit_data.norm;
+}
 
-        let reflected = reflect(dir_in, &effective_norm);
-        let dir = reflected + fuzz * random_in_unit_sphere();
+//Frame of the hit tangent around the effective normal, flipped as a whole for hits from inside
+fn shading_frame(hit_data:&HitData) -> Frame{
+        return Frame::from_tangent(&effective_normal(hit_data), &hit_data.tangent);
+}
 
-        if dir.dot(&effective_norm) > 0.0{
-                *out = dir.normalize();
-                return true;
+//Samples a visible microfacet normal and reflects on it, the weight is F * G2 / G1
+fn scatter_microfacet_reflection<F:Fn(f64) -> Vector3>(dir_in:&Vector3, frame:&Frame, ggx:&Ggx, out:&mut Vector3, weight:&mut Vector3, fresnel:F, sampler:&mut dyn Sampler) -> bool{
+        let wo = frame.to_local(&-*dir_in);
+        if wo.z <= 0.0{
+                return false;
+        }
+
+        let (u1, u2) = sampler.get_2d();
+        let m = ggx.sample_visible(&wo, u1, u2);
+        let wi = microfacet::reflect(&wo, &m);
+        if wi.z <= 0.0{
+                return false;
         }
 
-        return false;
+        *weight = weight.mult(&((ggx.g2(&wo, &wi) / ggx.g1(&wo)) * fresnel(wo.dot(&m))));
+        *out = frame.to_world(&wi).normalize();
+        return true;
 }
 
-fn scatter_glass(dir_in:&Vector3, hit_data:&HitData, refraction:f64, out:&mut Vector3) -> bool{
+fn scatter_glass(dir_in:&Vector3, hit_data:&HitData, refraction:f64, out:&mut Vector3, sampler:&mut dyn Sampler) -> bool{
         let effective_norm:Vector3;
         let r:f64;
 
@@ -329,11 +1416,135 @@ fn scatter_glass(dir_in:&Vector3, hit_data:&HitData, refraction:f64, out:&mut Ve
                 r = 1.0/refraction;
         }
 
-        *out = refract(dir_in, &effective_norm, r);
+        *out = refract(dir_in, &effective_norm, r, sampler.get_1d());
+        return true;
+}
+
+//Rough dielectric of Walter et al. 2007, a visible microfacet normal is sampled and the fresnel
+//term picks reflection or refraction on it so only G2 / G1 remains in the weight
+fn scatter_rough_glass(dir_in:&Vector3, hit_data:&HitData, refraction:f64, ggx:&Ggx, out:&mut Vector3, weight:&mut Vector3, sampler:&mut dyn Sampler) -> bool{
+        let frame = Frame::from_normal(&effective_normal(hit_data));
+        let wo = frame.to_local(&-*dir_in);
+        if wo.z <= 0.0{
+                return false;
+        }
+
+        let r = if hit_data.inside {refraction} else {1.0 / refraction};
+
+        let (u1, u2) = sampler.get_2d();
+        let m = ggx.sample_visible(&wo, u1, u2);
+        let cos = wo.dot(&m);
+
+        let wi = match microfacet::refract(&wo, &m, r){
+                Some(refracted) if microfacet::fresnel_dielectric(cos, r) <= sampler.get_1d() => {
+                        if refracted.z >= 0.0 {return false;}
+                        refracted
+                },
+                _ => {
+                        let reflected = microfacet::reflect(&wo, &m);
+                        if reflected.z <= 0.0 {return false;}
+                        reflected
+                },
+        };
+
+        *weight = (ggx.g2(&wo, &wi) / ggx.g1(&wo)) * *weight;
+        *out = frame.to_world(&wi).normalize();
         return true;
 }
 
-fn refract(dir_in:&Vector3, norm:&Vector3, refraction_relation:f64) -> Vector3{
+//Radiance factor and density of the rough dielectric lobes in the local frame, as sampled by
+//scatter_rough_glass and scatter_coated_glass: reflection is picked with the average reflectance
+//and the density of the visible normal carries over through the half vector of each lobe
+fn eval_pdf_rough_glass<F:Fn(f64) -> Vector3>(wo:&Vector3, wi:&Vector3, r:f64, ggx:&Ggx, fresnel:F) -> (Vector3, f64){
+        let none = (Vector3::new(0.0, 0.0, 0.0), 0.0);
+        if wo.z <= 0.0 || wi.z == 0.0{
+                return none;
+        }
+
+        //Total internal reflection reflects everything
+        let reflectance = |cos:f64| if microfacet::fresnel_dielectric(cos, r) >= 1.0 {Vector3::new(1.0, 1.0, 1.0)} else {fresnel(cos)};
+        let average = |c:&Vector3| (c.x + c.y + c.z) / 3.0;
+
+        if wi.z > 0.0{
+                let m = (*wo + *wi).normalize();
+                let cos = wo.dot(&m);
+                if cos <= 0.0{
+                        return none;
+                }
+                let f = reflectance(cos);
+                let value = (ggx.d(&m) * ggx.g2(wo, wi) / (4.0 * wo.z)) * f;
+                return (value, average(&f) * ggx.pdf_visible(wo, &m) / (4.0 * cos));
+        }
+
+        //Generalized half vector, pointing to the side of wo
+        let half = r * *wo + *wi;
+        if half.norm_squared() <= 0.0{
+                return none;
+        }
+        let mut m = half.normalize();
+        if m.z < 0.0{
+                m = -m;
+        }
+        let cos_o = wo.dot(&m);
+        let cos_i = wi.dot(&m);
+        if cos_o <= 0.0 || cos_i >= 0.0{
+                return none;
+        }
+
+        let t = Vector3::new(1.0, 1.0, 1.0) - reflectance(cos_o);
+        //Change of variables from the microfacet normal to the refracted direction
+        let jacobian = -cos_i / (cos_i + r * cos_o).powi(2);
+        let value = (ggx.d(&m) * ggx.g2(wo, wi) * cos_o * jacobian / wo.z) * t;
+        return (value, average(&t) * ggx.pdf_visible(wo, &m) * jacobian);
+}
+
+//Smooth or rough dielectric whose reflectance differs per channel, reflection is picked with the
+//average reflectance and the weight keeps the color
+fn scatter_coated_glass<F:Fn(f64) -> Vector3>(dir_in:&Vector3, hit_data:&HitData, refraction:f64, ggx:Option<&Ggx>, out:&mut Vector3, weight:&mut Vector3, fresnel:F, sampler:&mut dyn Sampler) -> bool{
+        let frame = Frame::from_normal(&effective_normal(hit_data));
+        let wo = frame.to_local(&-*dir_in);
+        if wo.z <= 0.0{
+                return false;
+        }
+
+        let r = if hit_data.inside {refraction} else {1.0 / refraction};
+
+        let m = match ggx{
+                Some(ggx) => {
+                        let (u1, u2) = sampler.get_2d();
+                        ggx.sample_visible(&wo, u1, u2)
+                },
+                None => Vector3::new(0.0, 0.0, 1.0),
+        };
+        let cos = wo.dot(&m);
+
+        let refracted = microfacet::refract(&wo, &m, r);
+        //Total internal reflection reflects everything
+        let reflectance = if refracted.is_some() {fresnel(cos)} else {Vector3::new(1.0, 1.0, 1.0)};
+        let probability = (reflectance.x + reflectance.y + reflectance.z) / 3.0;
+
+        let (wi, reflected) = match refracted{
+                Some(refracted) if sampler.get_1d() >= probability => {
+                        *weight = weight.mult(&((1.0 / (1.0 - probability)) * (Vector3::new(1.0, 1.0, 1.0) - reflectance)));
+                        (refracted, false)
+                },
+                _ => {
+                        *weight = weight.mult(&((1.0 / probability) * reflectance));
+                        (microfacet::reflect(&wo, &m), true)
+                },
+        };
+        if reflected != (wi.z > 0.0){
+                return false;
+        }
+
+        if let Some(ggx) = ggx{
+                *weight = (ggx.g2(&wo, &wi) / ggx.g1(&wo)) * *weight;
+        }
+        *out = frame.to_world(&wi).normalize();
+        return true;
+}
+
+fn refract(dir_in:&Vector3, norm:&Vector3, refraction_relation:f64, u:f64) -> Vector3{
         /*
         let cos = dir_in.dot(norm);
         let out_perpendicular = refraction_relation * (dir_in + cos.abs()*norm);
@@ -345,7 +1556,7 @@ fn refract(dir_in:&Vector3, norm:&Vector3, refraction_relation:f64) -> Vector3{
         let sin_theta = (1.0 - cos_theta*cos_theta).sqrt();
         let sin_theta_2 = refraction_relation*sin_theta;//.min(1.0);
 
-        if (sin_theta_2 > 1.0) || (reflectance(cos_theta, refraction_relation) > rand::thread_rng().gen::<f64>()){
+        if (sin_theta_2 > 1.0) || (reflectance(cos_theta, refraction_relation) > u){
                 //Impossible to cannot refract or reflectance chance is greater
                 return reflect(dir_in, norm);
         }
@@ -369,29 +1580,166 @@ fn reflectance2(cosine: f64, ref_idx: f64) -> f64 {
         r0 + (1.0-r0)*((1.0-cosine).powi(5))
 }
 
-fn random_in_unit_sphere() -> Vector3{
-    let mut rng = rand::thread_rng();
-    loop{
-            let p = Vector3::new(rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>());
-            if p.norm_squared() <= 1.0{
-                    return p;
-            }
-    }
+fn reflect(v:&Vector3, norm:&Vector3) -> Vector3{
+    return v - 2.0 * v.dot(norm) * norm;
 }
 
-fn random_in_hemisphere(norm:&Vector3) -> Vector3{
-        let v = random_in_unit_sphere();
-        if v.dot(norm) < 0.0{
-                return -v;
+#[cfg(test)]
+mod tests{
+        use super::*;
+        use crate::scene::objects::{Object, Sphere};
+        use crate::sampler::SamplerKind;
+
+        fn hit(object:&Object, inside:bool) -> HitData{
+                return HitData{
+                        point:Vector3::new(0.0, 0.0, 0.0),
+                        norm:Vector3::new(0.0, 0.0, 1.0),
+                        geometric_norm:Vector3::new(0.0, 0.0, 1.0),
+                        inside,
+                        distance:1.0,
+                        object,
+                        uv:(0.0, 0.0),
+                        tangent:Vector3::new(1.0, 0.0, 0.0),
+                        bitangent:Vector3::new(0.0, 1.0, 0.0),
+                };
         }
-        return v;
-}
 
-fn random_unit_vector() -> Vector3{
-    let v = random_in_unit_sphere();
-    return v.normalize();
-}
+        fn close(a:f64, b:f64) -> bool{
+                return (a - b).abs() <= 1e-6 * a.abs().max(b.abs()).max(1.0);
+        }
 
-fn reflect(v:&Vector3, norm:&Vector3) -> Vector3{
-    return v - 2.0 * v.dot(norm) * norm;
+        //Every sample of a non delta material reports the density pdf gives, and eval / pdf as weight
+        //unless lobes are picked with their own weights
+        fn check_sample_matches_eval_and_pdf(material:&Material, weight_is_eval_over_pdf:bool){
+                let object = Sphere::create(Vector3::new(0.0, 0.0, 0.0), 1.0, MaterialId(0));
+                let mut sampler = SamplerKind::Independent.create(1);
+                sampler.start_pixel_sample((0, 0), 0);
+                for inside in [false, true]{
+                        let hit_data = hit(&object, inside);
+                        let side = if inside {1.0} else {-1.0};
+                        let dir_in = Vector3::new(0.6, 0.0, 0.8 * side);
+                        let mut found = 0;
+                        for _ in 0..2000{
+                                let sample = match material.sample(&dir_in, &hit_data, &mut None, sampler.as_mut()){
+                                        Some(sample) => sample,
+                                        None => continue,
+                                };
+                                found += 1;
+                                assert!(!sample.delta);
+                                let pdf = material.pdf(&dir_in, &sample.direction, &hit_data, &None);
+                                assert!(sample.pdf > 0.0 && close(sample.pdf, pdf), "{} {}", sample.pdf, pdf);
+                                if !weight_is_eval_over_pdf{
+                                        continue;
+                                }
+                                let eval = material.eval(&dir_in, &sample.direction, &hit_data, &None);
+                                for i in 0..3{
+                                        assert!(close(sample.weight.component(i), eval.component(i) / pdf), "{} {}", sample.weight.component(i), eval.component(i) / pdf);
+                                }
+                        }
+                        assert!(found > 1000);
+                }
+        }
+
+        //Integral of pdf over the sphere, on a grid uniform in z and the angle, against the share of
+        //samples that aren't absorbed
+        fn check_pdf_integrates_to_sampled_fraction(material:&Material){
+                let object = Sphere::create(Vector3::new(0.0, 0.0, 0.0), 1.0, MaterialId(0));
+                let mut sampler = SamplerKind::Independent.create(2);
+                sampler.start_pixel_sample((0, 0), 0);
+                let cells = 1000;
+                for inside in [false, true]{
+                        let hit_data = hit(&object, inside);
+                        let side = if inside {1.0} else {-1.0};
+                        let dir_in = Vector3::new(0.6, 0.0, 0.8 * side);
+
+                        let mut integral = 0.0;
+                        for i in 0..cells{
+                                let z = -1.0 + 2.0 * (i as f64 + 0.5) / cells as f64;
+                                let radius = (1.0 - z * z).sqrt();
+                                for j in 0..cells{
+                                        let phi = 2.0 * std::f64::consts::PI * (j as f64 + 0.5) / cells as f64;
+                                        let dir = Vector3::new(radius * phi.cos(), radius * phi.sin(), z);
+                                        integral += material.pdf(&dir_in, &dir, &hit_data, &None);
+                                }
+                        }
+                        let integral = integral * 4.0 * std::f64::consts::PI / (cells * cells) as f64;
+
+                        let count = 100000;
+                        let sampled = (0..count).filter(|_| material.sample(&dir_in, &hit_data, &mut None, sampler.as_mut()).is_some()).count();
+                        let fraction = sampled as f64 / count as f64;
+                        assert!((integral - fraction).abs() < 0.01, "{} {}", integral, fraction);
+                }
+        }
+
+        #[test]
+        fn rough_glass_sample_matches_eval_and_pdf(){
+                check_sample_matches_eval_and_pdf(&Glass::create_rough(Texture::from(1.0), 1.5, Texture::from(0.4)), true);
+        }
+
+        #[test]
+        fn rough_glass_pdf_integrates_to_sampled_fraction(){
+                check_pdf_integrates_to_sampled_fraction(&Glass::create_rough(Texture::from(1.0), 1.5, Texture::from(0.4)));
+        }
+
+        #[test]
+        fn coated_rough_glass_sample_matches_eval_and_pdf(){
+                let film = ThinFilm::new(Texture::from(400.0), 1.33);
+                check_sample_matches_eval_and_pdf(&Glass::create_thin_film(1.5, 0.4, film), true);
+        }
+
+        fn rough_transmissive_principled() -> Material{
+                return Material::Principled(Principled{
+                        roughness:Texture::from(0.4),
+                        transmission:0.8,
+                        ..Principled::new(Texture::from(Vector3::new(0.9, 0.6, 0.3)))
+                });
+        }
+
+        #[test]
+        fn principled_transmission_sample_matches_pdf(){
+                check_sample_matches_eval_and_pdf(&rough_transmissive_principled(), false);
+        }
+
+        #[test]
+        fn principled_transmission_pdf_integrates_to_sampled_fraction(){
+                check_pdf_integrates_to_sampled_fraction(&rough_transmissive_principled());
+        }
+
+        //Lobes are picked with their own weights, on average they still give eval
+        #[test]
+        fn principled_transmission_weights_average_to_eval(){
+                let material = rough_transmissive_principled();
+                let object = Sphere::create(Vector3::new(0.0, 0.0, 0.0), 1.0, MaterialId(0));
+                let hit_data = hit(&object, false);
+                let dir_in = Vector3::new(0.6, 0.0, -0.8);
+                let mut sampler = SamplerKind::Independent.create(4);
+                sampler.start_pixel_sample((0, 0), 0);
+
+                //Both should be the directional albedo, sampled and integrated over a grid
+                let count = 400000;
+                let mut sampled = Vector3::new(0.0, 0.0, 0.0);
+                for _ in 0..count{
+                        if let Some(sample) = material.sample(&dir_in, &hit_data, &mut None, sampler.as_mut()){
+                                sampled += sample.weight;
+                        }
+                }
+                let sampled = 1.0 / count as f64 * sampled;
+
+                let cells = 1000;
+                let mut integral = Vector3::new(0.0, 0.0, 0.0);
+                for i in 0..cells{
+                        let z = -1.0 + 2.0 * (i as f64 + 0.5) / cells as f64;
+                        let radius = (1.0 - z * z).sqrt();
+                        for j in 0..cells{
+                                let phi = 2.0 * std::f64::consts::PI * (j as f64 + 0.5) / cells as f64;
+                                let dir = Vector3::new(radius * phi.cos(), radius * phi.sin(), z);
+                                integral += material.eval(&dir_in, &dir, &hit_data, &None);
+                        }
+                }
+                let integral = 4.0 * std::f64::consts::PI / (cells * cells) as f64 * integral;
+
+                for i in 0..3{
+                        assert!((sampled.component(i) - integral.component(i)).abs() < 0.01, "{} {}", sampled.component(i), integral.component(i));
+                }
+        }
 }
\ No newline at end of file
@@ -0,0 +1,372 @@
+use crate::vector3::Vector3;
+use crate::sampling::orthonormal_basis;
+use crate::spectrum;
+
+use std::f64::consts::PI;
+use std::ops::{Add, Sub, Mul, Div, Neg};
+
+//Microfacet model shared by the rough materials. Directions are in a local shading frame where
+//the normal is +z and both directions point away from the surface.
+
+//Orthonormal shading frame around a normal
+#[derive(Copy, Clone)]
+pub struct Frame{
+	pub tangent:Vector3,
+	pub bitangent:Vector3,
+	pub normal:Vector3,
+}
+
+impl Frame{
+	pub fn from_normal(normal:&Vector3) -> Frame{
+		let (tangent, bitangent) = orthonormal_basis(normal);
+		Frame{
+			tangent,
+			bitangent,
+			normal:*normal,
+		}
+	}
+
+	//Frame with the tangent given, tangent must be a unit vector orthogonal to normal
+	pub fn from_tangent(normal:&Vector3, tangent:&Vector3) -> Frame{
+		Frame{
+			tangent:*tangent,
+			bitangent:normal.cross(tangent),
+			normal:*normal,
+		}
+	}
+
+	//Tangent and bitangent turned around the normal by angle radians
+	pub fn rotated(&self, angle:f64) -> Frame{
+		let (sin, cos) = angle.sin_cos();
+		Frame{
+			tangent:cos * self.tangent + sin * self.bitangent,
+			bitangent:cos * self.bitangent - sin * self.tangent,
+			normal:self.normal,
+		}
+	}
+
+	pub fn to_local(&self, v:&Vector3) -> Vector3{
+		return Vector3::new(v.dot(&self.tangent), v.dot(&self.bitangent), v.dot(&self.normal));
+	}
+
+	pub fn to_world(&self, v:&Vector3) -> Vector3{
+		return v.x * self.tangent + v.y * self.bitangent + v.z * self.normal;
+	}
+}
+
+//GGX / Trowbridge-Reitz distribution, alpha_x and alpha_y differ for anisotropic surfaces
+#[derive(Copy, Clone)]
+pub struct Ggx{
+	pub alpha_x:f64,
+	pub alpha_y:f64,
+}
+
+impl Ggx{
+	pub fn isotropic(alpha:f64) -> Ggx{
+		Ggx{
+			alpha_x:alpha,
+			alpha_y:alpha,
+		}
+	}
+
+	//Perceptual roughness in [0, 1] to alpha, clamped so the distribution never becomes a delta
+	pub fn from_roughness(roughness:f64) -> Ggx{
+		return Ggx::isotropic(roughness_to_alpha(roughness));
+	}
+
+	//Burley's mapping, anisotropy in [0, 1) makes the distribution wider along the tangent so
+	//highlights stretch across it, like on metal brushed in the direction of the tangent
+	pub fn from_roughness_anisotropic(roughness:f64, anisotropy:f64) -> Ggx{
+		let aspect = (1.0 - 0.9 * anisotropy.clamp(0.0, 1.0)).sqrt();
+		let alpha = roughness * roughness;
+		Ggx{
+			alpha_x:(alpha / aspect).max(1e-4),
+			alpha_y:(alpha * aspect).max(1e-4),
+		}
+	}
+
+	//Distribution of normals
+	pub fn d(&self, m:&Vector3) -> f64{
+		if m.z <= 0.0 {return 0.0;}
+		let x = m.x / self.alpha_x;
+		let y = m.y / self.alpha_y;
+		let e = x * x + y * y + m.z * m.z;
+		return 1.0 / (PI * self.alpha_x * self.alpha_y * e * e);
+	}
+
+	//Smith lambda of the height correlated masking function
+	pub fn lambda(&self, w:&Vector3) -> f64{
+		if w.z == 0.0 {return f64::INFINITY;}
+		let a2 = (self.alpha_x * w.x).powi(2) + (self.alpha_y * w.y).powi(2);
+		return 0.5 * (-1.0 + (1.0 + a2 / (w.z * w.z)).sqrt());
+	}
+
+	pub fn g1(&self, w:&Vector3) -> f64{
+		return 1.0 / (1.0 + self.lambda(w));
+	}
+
+	//Height correlated masking-shadowing
+	pub fn g2(&self, wo:&Vector3, wi:&Vector3) -> f64{
+		return 1.0 / (1.0 + self.lambda(wo) + self.lambda(wi));
+	}
+
+	//Samples a normal visible from wo (Heitz 2018), u1 and u2 uniform in [0, 1)
+	pub fn sample_visible(&self, wo:&Vector3, u1:f64, u2:f64) -> Vector3{
+		//Stretch to the hemisphere configuration
+		let vh = Vector3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).normalize();
+
+		let length_squared = vh.x * vh.x + vh.y * vh.y;
+		let t1 = if length_squared > 0.0 {1.0 / length_squared.sqrt() * Vector3::new(-vh.y, vh.x, 0.0)} else {Vector3::new(1.0, 0.0, 0.0)};
+		let t2 = vh.cross(&t1);
+
+		//Point on the projected disk, squashed where the hemisphere is hidden
+		let r = u1.sqrt();
+		let phi = 2.0 * PI * u2;
+		let p1 = r * phi.cos();
+		let mut p2 = r * phi.sin();
+		let s = 0.5 * (1.0 + vh.z);
+		p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * p2;
+
+		let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+
+		//Back to the ellipsoid configuration
+		return Vector3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).normalize();
+	}
+
+	//Density of sample_visible over normals
+	pub fn pdf_visible(&self, wo:&Vector3, m:&Vector3) -> f64{
+		if wo.z <= 0.0 {return 0.0;}
+		return self.g1(wo) * wo.dot(m).max(0.0) * self.d(m) / wo.z;
+	}
+
+	pub fn is_smooth(&self) -> bool{
+		return self.alpha_x.max(self.alpha_y) < 1e-3;
+	}
+}
+
+pub fn roughness_to_alpha(roughness:f64) -> f64{
+	return (roughness * roughness).max(1e-4);
+}
+
+pub fn reflect(wo:&Vector3, m:&Vector3) -> Vector3{
+	return 2.0 * wo.dot(m) * m - *wo;
+}
+
+//Refraction of wo through a surface with normal m, r is the ratio of the indices of the incident
+//and the transmitted side. None on total internal reflection.
+pub fn refract(wo:&Vector3, m:&Vector3, r:f64) -> Option<Vector3>{
+	let cos_i = wo.dot(m);
+	let sin2_t = r * r * (1.0 - cos_i * cos_i).max(0.0);
+	if sin2_t >= 1.0 {return None;}
+
+	let cos_t = (1.0 - sin2_t).sqrt();
+	return Some((r * cos_i - cos_t) * m - r * wo);
+}
+
+//Unpolarized reflectance of a dielectric, r as in refract
+pub fn fresnel_dielectric(cos_i:f64, r:f64) -> f64{
+	let cos_i = cos_i.clamp(0.0, 1.0);
+	let sin2_t = r * r * (1.0 - cos_i * cos_i);
+	if sin2_t >= 1.0 {return 1.0;}
+
+	let cos_t = (1.0 - sin2_t).sqrt();
+	let rs = (r * cos_i - cos_t) / (r * cos_i + cos_t);
+	let rp = (cos_i - r * cos_t) / (cos_i + r * cos_t);
+	return 0.5 * (rs * rs + rp * rp);
+}
+
+//Complex index of refraction of a metal, one value per RGB channel
+#[derive(Copy, Clone, Debug)]
+pub struct Conductor{
+	pub eta:Vector3,
+	pub k:Vector3,
+}
+
+impl Conductor{
+	//Measured values sampled at about 650, 550 and 450nm
+	pub const GOLD:Conductor = Conductor{eta:Vector3::new(0.143, 0.374, 1.442), k:Vector3::new(3.983, 2.385, 1.603)};
+	pub const COPPER:Conductor = Conductor{eta:Vector3::new(0.200, 0.924, 1.102), k:Vector3::new(3.912, 2.452, 2.142)};
+	pub const ALUMINUM:Conductor = Conductor{eta:Vector3::new(1.657, 0.880, 0.521), k:Vector3::new(9.224, 6.270, 4.837)};
+	pub const SILVER:Conductor = Conductor{eta:Vector3::new(0.155, 0.117, 0.138), k:Vector3::new(4.828, 3.122, 2.147)};
+
+	pub fn fresnel(&self, cos:f64) -> Vector3{
+		return Vector3::new(
+			fresnel_conductor(cos, self.eta.x, self.k.x),
+			fresnel_conductor(cos, self.eta.y, self.k.y),
+			fresnel_conductor(cos, self.eta.z, self.k.z));
+	}
+
+	//Index at a wavelength in nm, interpolated between the three measured values
+	pub fn ior_at(&self, lambda:f64) -> Complex{
+		let t = ((lambda - 450.0) / 100.0).clamp(0.0, 2.0);
+		let (low, high, t) = if t < 1.0 {(2, 1, t)} else {(1, 0, t - 1.0)};
+		let mix = |v:&Vector3| (1.0 - t) * v.component(low) + t * v.component(high);
+		return Complex::new(mix(&self.eta), mix(&self.k));
+	}
+}
+
+//Unpolarized reflectance of a conductor for one wavelength
+pub fn fresnel_conductor(cos:f64, eta:f64, k:f64) -> f64{
+	let cos = cos.clamp(0.0, 1.0);
+	let cos2 = cos * cos;
+	let sin2 = 1.0 - cos2;
+
+	let t0 = eta * eta - k * k - sin2;
+	let a2b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+	let t1 = a2b2 + cos2;
+	let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+	let t2 = 2.0 * cos * a;
+	let rs = (t1 - t2) / (t1 + t2);
+
+	let t3 = cos2 * a2b2 + sin2 * sin2;
+	let t4 = t2 * sin2;
+	let rp = rs * (t3 - t4) / (t3 + t4);
+
+	return 0.5 * (rp + rs);
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Complex{
+	pub re:f64,
+	pub im:f64,
+}
+
+impl Complex{
+	pub const fn new(re:f64, im:f64) -> Complex{
+		Complex{re, im}
+	}
+
+	pub const fn real(re:f64) -> Complex{
+		Complex{re, im:0.0}
+	}
+
+	pub fn norm_squared(&self) -> f64{
+		return self.re * self.re + self.im * self.im;
+	}
+
+	pub fn arg(&self) -> f64{
+		return self.im.atan2(self.re);
+	}
+
+	//Principal square root
+	pub fn sqrt(&self) -> Complex{
+		let norm = self.norm_squared().sqrt();
+		let re = (0.5 * (norm + self.re)).max(0.0).sqrt();
+		let im = (0.5 * (norm - self.re)).max(0.0).sqrt();
+		return Complex::new(re, if self.im < 0.0 {-im} else {im});
+	}
+
+	//e^(i * self)
+	pub fn exp_i(&self) -> Complex{
+		let scale = (-self.im).exp();
+		return Complex::new(scale * self.re.cos(), scale * self.re.sin());
+	}
+}
+
+impl Add for Complex{
+	type Output = Complex;
+	fn add(self, other:Complex) -> Complex{
+		return Complex::new(self.re + other.re, self.im + other.im);
+	}
+}
+
+impl Sub for Complex{
+	type Output = Complex;
+	fn sub(self, other:Complex) -> Complex{
+		return Complex::new(self.re - other.re, self.im - other.im);
+	}
+}
+
+impl Mul for Complex{
+	type Output = Complex;
+	fn mul(self, other:Complex) -> Complex{
+		return Complex::new(self.re * other.re - self.im * other.im, self.re * other.im + self.im * other.re);
+	}
+}
+
+impl Div for Complex{
+	type Output = Complex;
+	fn div(self, other:Complex) -> Complex{
+		let d = other.norm_squared();
+		return Complex::new((self.re * other.re + self.im * other.im) / d, (self.im * other.re - self.re * other.im) / d);
+	}
+}
+
+impl Neg for Complex{
+	type Output = Complex;
+	fn neg(self) -> Complex{
+		return Complex::new(-self.re, -self.im);
+	}
+}
+
+//Amplitude reflection coefficients (s, p) from index n_i into n_t, and the cosine on the n_t side
+fn fresnel_amplitudes(cos_i:Complex, n_i:Complex, n_t:Complex) -> (Complex, Complex, Complex){
+	let one = Complex::real(1.0);
+	let ratio = n_i / n_t;
+	let cos_t = (one - ratio * ratio * (one - cos_i * cos_i)).sqrt();
+
+	let rs = (n_i * cos_i - n_t * cos_t) / (n_i * cos_i + n_t * cos_t);
+	let rp = (n_t * cos_i - n_i * cos_t) / (n_t * cos_i + n_i * cos_t);
+	return (rs, rp, cos_t);
+}
+
+//Reflectance of a film of index film and thickness nanometers between a dielectric of index
+//outside and the substrate, for one wavelength in nm (Airy summation)
+pub fn fresnel_thin_film(cos:f64, outside:f64, film:f64, thickness:f64, substrate:Complex, lambda:f64) -> f64{
+	let (n1, n2) = (Complex::real(outside), Complex::real(film));
+	let (r12s, r12p, cos2) = fresnel_amplitudes(Complex::real(cos.clamp(0.0, 1.0)), n1, n2);
+	let (r23s, r23p, _) = fresnel_amplitudes(cos2, n2, substrate);
+
+	//Round trip phase through the film
+	let phase = (Complex::real(4.0 * PI * thickness / lambda) * n2 * cos2).exp_i();
+	let airy = |r12:Complex, r23:Complex| ((r12 + r23 * phase) / (Complex::real(1.0) + r12 * r23 * phase)).norm_squared();
+	return (0.5 * (airy(r12s, r23s) + airy(r12p, r23p))).clamp(0.0, 1.0);
+}
+
+//RGB reflectance of the same film with the interference integrated against the color matching
+//functions in closed form (Belcour and Barla 2017), avoids the banding of three point samples
+pub fn fresnel_thin_film_rgb(cos:f64, outside:f64, film:f64, thickness:f64, substrate:Complex) -> Vector3{
+	//A vanishing film fades to the bare substrate
+	let t = (thickness / 30.0).clamp(0.0, 1.0);
+	let film = outside + (film - outside) * t * t * (3.0 - 2.0 * t);
+
+	let (n1, n2) = (Complex::real(outside), Complex::real(film));
+	let (r12s, r12p, cos2) = fresnel_amplitudes(Complex::real(cos.clamp(0.0, 1.0)), n1, n2);
+	let (r23s, r23p, _) = fresnel_amplitudes(cos2, n2, substrate);
+
+	//Optical path difference of one round trip
+	let opd = 2.0 * film * thickness * cos2.re;
+
+	let mut xyz = Vector3::new(0.0, 0.0, 0.0);
+	for (r12, r23) in [(r12s, r23s), (r12p, r23p)]{
+		let r12_2 = r12.norm_squared();
+		let r23_2 = r23.norm_squared();
+		let t121 = 1.0 - r12_2;
+		let r123 = (r12_2 * r23_2).sqrt();
+		let rs = t121 * t121 * r23_2 / (1.0 - r12_2 * r23_2).max(1e-6);
+		let phi = PI - r12.arg() + r23.arg();
+
+		//Constant term then the first orders of the series
+		xyz += (0.5 * (r12_2 + rs)) * film_sensitivity(0.0, 0.0);
+		let mut c = rs - t121;
+		for m in 1..=3{
+			c *= r123;
+			xyz += c * film_sensitivity(m as f64 * opd, m as f64 * phi);
+		}
+	}
+
+	let rgb = spectrum::xyz_to_rgb(&xyz);
+	let white = spectrum::xyz_to_rgb(&film_sensitivity(0.0, 0.0));
+	return Vector3::new((rgb.x / white.x).clamp(0.0, 1.0), (rgb.y / white.y).clamp(0.0, 1.0), (rgb.z / white.z).clamp(0.0, 1.0));
+}
+
+//Fourier transform of the gaussian fit of the color matching functions at an optical path difference in nm
+fn film_sensitivity(opd:f64, shift:f64) -> Vector3{
+	let phase = 2.0 * PI * opd * 1e-9;
+	let term = |value:f64, position:f64, variance:f64| value * (2.0 * PI * variance).sqrt() * (position * phase + shift).cos() * (-variance * phase * phase).exp();
+
+	let x = term(5.4856e-13, 1.6810e6, 4.3278e9) + term(9.7470e-14, 2.2399e6, 4.5282e9);
+	let y = term(4.4201e-13, 1.7953e6, 9.3046e9);
+	let z = term(5.2481e-13, 2.2084e6, 6.6121e9);
+	return 1.0 / 1.0685e-7 * Vector3::new(x, y, z);
+}
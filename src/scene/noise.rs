@@ -0,0 +1,158 @@
+use crate::vector3::Vector3;
+
+//Gradient noise and cellular noise in 3D. Lattice values come from an integer hash instead of a
+//permutation table so the noise is the same everywhere and needs no initialization.
+
+fn hash(x:i32, y:i32, z:i32, seed:u32) -> u32{
+	let mut h = seed ^ (x as u32).wrapping_mul(0x8da6b343) ^ (y as u32).wrapping_mul(0xd8163841) ^ (z as u32).wrapping_mul(0xcb1ab31f);
+	h ^= h >> 16;
+	h = h.wrapping_mul(0x7feb352d);
+	h ^= h >> 15;
+	h = h.wrapping_mul(0x846ca68b);
+	h ^= h >> 16;
+	return h;
+}
+
+//Uniform value in [0, 1) from a hash
+fn hash_unit(h:u32) -> f64{
+	return (h >> 8) as f64 / (1u32 << 24) as f64;
+}
+
+//Dot product with one of the 12 cube edge directions of improved Perlin noise
+fn gradient(h:u32, x:f64, y:f64, z:f64) -> f64{
+	match h % 12{
+		0 => x + y,
+		1 => -x + y,
+		2 => x - y,
+		3 => -x - y,
+		4 => x + z,
+		5 => -x + z,
+		6 => x - z,
+		7 => -x - z,
+		8 => y + z,
+		9 => -y + z,
+		10 => y - z,
+		_ => -y - z,
+	}
+}
+
+fn fade(t:f64) -> f64{
+	return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+fn lerp(t:f64, a:f64, b:f64) -> f64{
+	return a + t * (b - a);
+}
+
+//Improved Perlin noise, roughly in [-1, 1] and 0 at every lattice point
+pub fn perlin(p:&Vector3) -> f64{
+	let xi = p.x.floor();
+	let yi = p.y.floor();
+	let zi = p.z.floor();
+	let x = p.x - xi;
+	let y = p.y - yi;
+	let z = p.z - zi;
+	//Far away points saturate the cast, the lattice offsets wrap around instead of overflowing
+	let (xi, yi, zi) = (xi as i32, yi as i32, zi as i32);
+
+	let u = fade(x);
+	let v = fade(y);
+	let w = fade(z);
+
+	let g = |dx:i32, dy:i32, dz:i32| gradient(hash(xi.wrapping_add(dx), yi.wrapping_add(dy), zi.wrapping_add(dz), 0), x - dx as f64, y - dy as f64, z - dz as f64);
+
+	return lerp(w,
+		lerp(v, lerp(u, g(0, 0, 0), g(1, 0, 0)), lerp(u, g(0, 1, 0), g(1, 1, 0))),
+		lerp(v, lerp(u, g(0, 0, 1), g(1, 0, 1)), lerp(u, g(0, 1, 1), g(1, 1, 1))));
+}
+
+//Fractal brownian motion, octaves of perlin noise doubling the frequency and halving the amplitude
+pub fn fbm(p:&Vector3, octaves:u32) -> f64{
+	let mut sum = 0.0;
+	let mut amplitude = 1.0;
+	let mut point = *p;
+
+	for _ in 0..octaves{
+		sum += amplitude * perlin(&point);
+		amplitude *= 0.5;
+		point = 2.0 * point;
+	}
+	return sum;
+}
+
+//Like fbm but with the absolute value of every octave, gives creases where the noise crosses 0
+pub fn turbulence(p:&Vector3, octaves:u32) -> f64{
+	let mut sum = 0.0;
+	let mut amplitude = 1.0;
+	let mut point = *p;
+
+	for _ in 0..octaves{
+		sum += amplitude * perlin(&point).abs();
+		amplitude *= 0.5;
+		point = 2.0 * point;
+	}
+	return sum;
+}
+
+//Result of a cellular noise lookup
+pub struct Voronoi{
+	//Distance to the closest and second closest feature points
+	pub f1:f64,
+	pub f2:f64,
+	//Random value in [0, 1) identifying the cell of the closest feature point
+	pub cell:f64,
+}
+
+//Worley noise with one feature point per unit cell, jitter 0 gives a regular grid and 1 the classic look
+pub fn voronoi(p:&Vector3, jitter:f64) -> Voronoi{
+	let cell = Vector3::new(p.x.floor(), p.y.floor(), p.z.floor());
+	//Cells are placed from the floored coordinates and only hashed by the integers, which saturate
+	//and wrap around for far away points
+	let (xi, yi, zi) = (cell.x as i32, cell.y as i32, cell.z as i32);
+
+	let mut result = Voronoi{
+		f1:f64::MAX,
+		f2:f64::MAX,
+		cell:0.0,
+	};
+
+	for dz in -1..=1{
+		for dy in -1..=1{
+			for dx in -1..=1{
+				let (cx, cy, cz) = (xi.wrapping_add(dx), yi.wrapping_add(dy), zi.wrapping_add(dz));
+				let feature = Vector3::new(
+					cell.x + dx as f64 + 0.5 + jitter * (hash_unit(hash(cx, cy, cz, 1)) - 0.5),
+					cell.y + dy as f64 + 0.5 + jitter * (hash_unit(hash(cx, cy, cz, 2)) - 0.5),
+					cell.z + dz as f64 + 0.5 + jitter * (hash_unit(hash(cx, cy, cz, 3)) - 0.5));
+				let distance = (feature - *p).norm();
+
+				if distance < result.f1{
+					result.f2 = result.f1;
+					result.f1 = distance;
+					result.cell = hash_unit(hash(cx, cy, cz, 4));
+				}else if distance < result.f2{
+					result.f2 = distance;
+				}
+			}
+		}
+	}
+	return result;
+}
+
+#[cfg(test)]
+mod tests{
+	use super::*;
+
+	//Grazing hits on an infinite plane land this far out
+	#[test]
+	fn far_points_do_not_overflow(){
+		for far in [i32::MAX as f64, i32::MAX as f64 + 0.5, i32::MIN as f64 - 0.5, 1e12, -1e12, 1e300]{
+			for p in [Vector3::new(far, 0.5, 0.5), Vector3::new(0.5, far, far)]{
+				assert!(perlin(&p).is_finite());
+				assert!(fbm(&p, 4).is_finite());
+				let v = voronoi(&p, 1.0);
+				assert!(v.f1.is_finite() && v.f1 <= v.f2);
+			}
+		}
+	}
+}
@@ -0,0 +1,74 @@
+use crate::vector3::Vector3;
+use crate::intersection::{HitData, tangent_frame};
+use super::textures::Texture;
+
+//Detail added to the shading normal of a hit, the geometric normal is left as is
+#[derive(Clone)]
+pub enum NormalMap{
+	//Tangent space image, red along the tangent, green along the bitangent and blue along the
+	//normal. Load it with Texture::image_linear, the values are directions and not colors.
+	Image{texture:Texture, strength:f64},
+	//Scalar height texture, differentiated over step both in uv and in world units
+	Bump{height:Texture, strength:f64, step:f64},
+}
+
+impl NormalMap{
+	pub fn image(texture:Texture, strength:f64) -> NormalMap{
+		return NormalMap::Image{
+			texture,
+			strength,
+		};
+	}
+
+	pub fn bump(height:Texture, strength:f64) -> NormalMap{
+		return NormalMap::Bump{
+			height,
+			strength,
+			step:1e-3,
+		};
+	}
+
+	//Perturbed normal in the tangent frame of the hit
+	fn local_normal(&self, hit_data:&HitData) -> Vector3{
+		match self{
+			NormalMap::Image{texture, strength} => {
+				let color = texture.evaluate(hit_data);
+				return Vector3::new(strength * (2.0 * color.x - 1.0), strength * (2.0 * color.y - 1.0), 2.0 * color.z - 1.0);
+			},
+			NormalMap::Bump{height, strength, step} => {
+				let center = height.evaluate_scalar(hit_data);
+				let slope = |direction:&Vector3, du:f64, dv:f64| {
+					let shifted = HitData{
+						point:hit_data.point + *step * direction,
+						uv:(hit_data.uv.0 + du, hit_data.uv.1 + dv),
+						..*hit_data
+					};
+					(height.evaluate_scalar(&shifted) - center) / step
+				};
+
+				let dhdu = slope(&hit_data.tangent, *step, 0.0);
+				let dhdv = slope(&hit_data.bitangent, 0.0, *step);
+				return Vector3::new(-strength * dhdu, -strength * dhdv, 1.0);
+			},
+		}
+	}
+
+	//Replaces the shading normal and tangent frame of the hit
+	pub fn apply(&self, hit_data:&mut HitData){
+		let local = self.local_normal(hit_data);
+		let mut norm = local.x * hit_data.tangent + local.y * hit_data.bitangent + local.z * hit_data.norm;
+		if norm.norm_squared() < 1e-12{
+			return;
+		}
+		norm = norm.normalize();
+
+		//A normal facing away from the surface would shade it from behind, bend it back up
+		let cos = norm.dot(&hit_data.geometric_norm);
+		if cos < 0.01{
+			norm = (norm + (0.01 - cos) * hit_data.geometric_norm).normalize();
+		}
+
+		(hit_data.tangent, hit_data.bitangent) = tangent_frame(&norm, &hit_data.tangent);
+		hit_data.norm = norm;
+	}
+}
@@ -0,0 +1,289 @@
+use crate::vector3::Vector3;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+use super::Scene;
+use super::objects::Mesh;
+
+//Wavefront OBJ import. Every group (g/o) and every usemtl switch becomes its own mesh so each one
+//can carry a material, material names are resolved with Scene::get_material and mtllib is ignored.
+pub fn load_obj(scene:&mut Scene, path:&str, default_material:&String) -> io::Result<usize>{
+	let file = File::open(path)?;
+	return load_obj_from_reader(scene, BufReader::new(file), default_material);
+}
+
+pub fn load_obj_from_reader<R:BufRead>(scene:&mut Scene, reader:R, default_material:&String) -> io::Result<usize>{
+	let mut positions:Vec<Vector3> = Vec::new();
+	let mut normals:Vec<Vector3> = Vec::new();
+	let mut uvs:Vec<(f64, f64)> = Vec::new();
+
+	let mut builder = MeshBuilder::new(default_material.clone());
+	let mut mesh_count = 0;
+
+	for (line_number, line) in reader.lines().enumerate(){
+		let line = line?;
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {continue;}
+
+		let mut tokens = line.split_whitespace();
+		let keyword = tokens.next().unwrap_or("");
+
+		match keyword{
+			"v" => {
+				let v = parse_floats(&mut tokens, 3, line_number)?;
+				positions.push(Vector3::new(v[0], v[1], v[2]));
+			},
+			"vn" => {
+				let v = parse_floats(&mut tokens, 3, line_number)?;
+				normals.push(Vector3::new(v[0], v[1], v[2]));
+			},
+			"vt" => {
+				let v = parse_floats(&mut tokens, 2, line_number)?;
+				uvs.push((v[0], v[1]));
+			},
+			"f" => {
+				let mut corners:Vec<Corner> = Vec::new();
+				for token in tokens{
+					corners.push(parse_corner(token, positions.len(), uvs.len(), normals.len(), line_number)?);
+				}
+				if corners.len() < 3{
+					return Err(invalid_data(line_number, "face with less than 3 vertices"));
+				}
+
+				//Fan triangulation
+				for i in 1..corners.len() - 1{
+					builder.add_triangle([corners[0], corners[i], corners[i + 1]], &positions, &normals, &uvs);
+				}
+			},
+			"g" | "o" => {
+				mesh_count += builder.flush(scene);
+			},
+			"usemtl" => {
+				mesh_count += builder.flush(scene);
+				builder.material = tokens.next().map(String::from).unwrap_or(default_material.clone());
+			},
+			_ => {
+				//s, mtllib and unsupported statements
+			},
+		}
+	}
+
+	mesh_count += builder.flush(scene);
+	return Ok(mesh_count);
+}
+
+//Resolved 0 based indices of a face corner
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+struct Corner{
+	position:usize,
+	uv:Option<usize>,
+	normal:Option<usize>,
+}
+
+struct MeshBuilder{
+	material:String,
+	positions:Vec<Vector3>,
+	normals:Vec<Vector3>,
+	uvs:Vec<(f64, f64)>,
+	triangles:Vec<[u32; 3]>,
+	vertices:HashMap<Corner, u32>,
+	//Obj position of each vertex, vertices split by their uvs or normals share it
+	sources:Vec<usize>,
+	all_normals:bool,
+	all_uvs:bool,
+}
+
+impl MeshBuilder{
+	fn new(material:String) -> MeshBuilder{
+		MeshBuilder{
+			material,
+			positions:Vec::new(),
+			normals:Vec::new(),
+			uvs:Vec::new(),
+			triangles:Vec::new(),
+			vertices:HashMap::new(),
+			sources:Vec::new(),
+			all_normals:true,
+			all_uvs:true,
+		}
+	}
+
+	fn add_triangle(&mut self, corners:[Corner; 3], positions:&[Vector3], normals:&[Vector3], uvs:&[(f64, f64)]){
+		let mut triangle = [0u32; 3];
+
+		for (i, corner) in corners.iter().enumerate(){
+			let next_index = self.positions.len() as u32;
+			let index = *self.vertices.entry(*corner).or_insert(next_index);
+
+			if index == next_index{
+				self.positions.push(positions[corner.position]);
+				self.sources.push(corner.position);
+				match corner.normal{
+					Some(n) => self.normals.push(normals[n]),
+					None => {
+						self.all_normals = false;
+						self.normals.push(Vector3::default());
+					},
+				}
+				match corner.uv{
+					Some(t) => self.uvs.push(uvs[t]),
+					None => {
+						self.all_uvs = false;
+						self.uvs.push((0.0, 0.0));
+					},
+				}
+			}
+			triangle[i] = index;
+		}
+
+		self.triangles.push(triangle);
+	}
+
+	//Adds the current mesh to the scene and starts a new one, returns the number of meshes added
+	fn flush(&mut self, scene:&mut Scene) -> usize{
+		if self.triangles.is_empty() {return 0;}
+
+		let normals = if self.all_normals {std::mem::take(&mut self.normals)} else {self.smooth_normals()};
+		let uvs = if self.all_uvs {std::mem::take(&mut self.uvs)} else {Vec::new()};
+		let mesh = Mesh::new(std::mem::take(&mut self.positions), normals, uvs, std::mem::take(&mut self.triangles));
+		scene.create_mesh(mesh, &self.material);
+
+		*self = MeshBuilder::new(self.material.clone());
+		return 1;
+	}
+
+	//Area weighted normals for faces without vn, summed per obj position so uv seams stay smooth
+	fn smooth_normals(&self) -> Vec<Vector3>{
+		let mut sums:HashMap<usize, Vector3> = HashMap::new();
+		for triangle in &self.triangles{
+			let [p0, p1, p2] = triangle.map(|vertex| self.positions[vertex as usize]);
+			let face_normal = (p1 - p0).cross(&(p2 - p0));
+			for vertex in triangle{
+				*sums.entry(self.sources[*vertex as usize]).or_default() += face_normal;
+			}
+		}
+
+		return self.sources.iter().map(|source| {
+			let sum = sums[source];
+			if sum.norm_squared() > 0.0 {sum.normalize()} else {sum}
+		}).collect();
+	}
+}
+
+fn invalid_data(line_number:usize, message:&str) -> io::Error{
+	return io::Error::new(io::ErrorKind::InvalidData, format!("obj line {}: {}", line_number + 1, message));
+}
+
+fn parse_floats<'a, I:Iterator<Item = &'a str>>(tokens:&mut I, count:usize, line_number:usize) -> io::Result<Vec<f64>>{
+	let mut values = Vec::with_capacity(count);
+	for _ in 0..count{
+		let token = tokens.next().ok_or_else(|| invalid_data(line_number, "missing coordinate"))?;
+		let value = token.parse::<f64>().map_err(|_| invalid_data(line_number, "invalid number"))?;
+		values.push(value);
+	}
+	return Ok(values);
+}
+
+//Obj indices start at 1 and negative values are relative to the end of the list
+fn resolve_index(token:&str, count:usize, line_number:usize) -> io::Result<usize>{
+	let index = token.parse::<i64>().map_err(|_| invalid_data(line_number, "invalid index"))?;
+	let resolved = if index < 0 {count as i64 + index} else {index - 1};
+
+	if resolved < 0 || resolved >= count as i64{
+		return Err(invalid_data(line_number, "index out of range"));
+	}
+	return Ok(resolved as usize);
+}
+
+//Parses v, v/vt, v//vn and v/vt/vn
+fn parse_corner(token:&str, position_count:usize, uv_count:usize, normal_count:usize, line_number:usize) -> io::Result<Corner>{
+	let mut parts = token.split('/');
+
+	let position = resolve_index(parts.next().unwrap_or(""), position_count, line_number)?;
+
+	let uv = match parts.next(){
+		Some(t) if !t.is_empty() => Some(resolve_index(t, uv_count, line_number)?),
+		_ => None,
+	};
+
+	let normal = match parts.next(){
+		Some(n) if !n.is_empty() => Some(resolve_index(n, normal_count, line_number)?),
+		_ => None,
+	};
+
+	return Ok(Corner{position, uv, normal});
+}
+
+#[cfg(test)]
+mod tests{
+	use super::*;
+	use crate::scene::Camera;
+	use crate::scene::objects::Shape;
+	use std::io::Cursor;
+
+	fn load(source:&str) -> (Scene, usize){
+		let camera = Camera::new(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), 60.0);
+		let mut scene = Scene::new(camera);
+		let count = load_obj_from_reader(&mut scene, Cursor::new(source), &String::from("default")).unwrap();
+		return (scene, count);
+	}
+
+	fn last_mesh(scene:&Scene) -> &Mesh{
+		match scene.shape(scene.handles().last().unwrap()){
+			Shape::Mesh(m) => return m,
+			_ => panic!("not a mesh"),
+		}
+	}
+
+	const SQUARE:&str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+	#[test]
+	fn polygons_are_fan_triangulated(){
+		let (scene, count) = load("v 0 0 0\nv 1 0 0\nv 2 1 0\nv 1 2 0\nv 0 1 0\nf 1 2 3 4 5\n");
+		assert_eq!(count, 1);
+		assert_eq!(last_mesh(&scene).triangles, vec![[0, 1, 2], [0, 2, 3], [0, 3, 4]]);
+	}
+
+	#[test]
+	fn negative_indices_count_from_the_end(){
+		let (relative, _) = load(&format!("{}f -4 -3 -2 -1\n", SQUARE));
+		let (absolute, _) = load(&format!("{}f 1 2 3 4\n", SQUARE));
+		assert_eq!(last_mesh(&relative).triangles, last_mesh(&absolute).triangles);
+
+		let mesh = last_mesh(&relative);
+		assert_eq!(mesh.positions[0].x, 0.0);
+		assert_eq!(mesh.positions[2].y, 1.0);
+	}
+
+	#[test]
+	fn out_of_range_index_is_an_error(){
+		let camera = Camera::new(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), 60.0);
+		let mut scene = Scene::new(camera);
+		for source in ["v 0 0 0\nf 1 2 3\n", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf -4 1 2\n", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 0 1 2\n"]{
+			let error = load_obj_from_reader(&mut scene, Cursor::new(source), &String::from("default")).unwrap_err();
+			assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+		}
+	}
+
+	#[test]
+	fn missing_normals_are_generated(){
+		let (scene, _) = load(&format!("{}vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nvt 0.5 0.5\nf 1/1 2/2 3/3\nf 1/5 3/3 4/4\n", SQUARE));
+		let mesh = last_mesh(&scene);
+		assert!(mesh.has_normals());
+		assert_eq!(mesh.normals.len(), mesh.positions.len());
+		for normal in &mesh.normals{
+			assert!((normal.z - 1.0).abs() < 1e-12);
+		}
+	}
+
+	#[test]
+	fn given_normals_are_kept(){
+		let (scene, _) = load(&format!("{}vn 0 0 -1\nf 1//1 2//1 3//1 4//1\n", SQUARE));
+		let mesh = last_mesh(&scene);
+		for normal in &mesh.normals{
+			assert_eq!(normal.z, -1.0);
+		}
+	}
+}
@@ -2,24 +2,49 @@
 //use na::Vector3;
 use crate::vector3::Vector3;
 
-use super::materials::Material;
+use crate::bvh::{Aabb, Bvh};
+use crate::matrix4::Transform;
+use std::sync::Arc;
+
+use super::materials::MaterialId;
+
+//Stable reference to an object of a scene, see Scene::object
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ObjectHandle(pub(crate) u32);
+
+impl ObjectHandle{
+        pub fn index(&self) -> usize{
+                return self.0 as usize;
+        }
+}
 
 pub struct Object{
         pub shape:Shape,
-        pub material:Material,
+        pub material:MaterialId,
 }
 
+#[derive(Clone)]
 pub enum Shape{
         Sphere(Sphere),
+        Plane(Plane),
+        Quad(Quad),
+        Cuboid(Cuboid),
+        Cylinder(Cylinder),
+        Cone(Cone),
+        Disk(Disk),
+        Capsule(Capsule),
+        Mesh(Arc<Mesh>),
+        Instance(Instance),
 }
 
+#[derive(Copy, Clone)]
 pub struct Sphere {
         pub position:Vector3,
         pub radius:f64,
 }
 
 impl Sphere{
-        pub fn create(position:Vector3, radius:f64, material:Material) -> Object{
+        pub fn create(position:Vector3, radius:f64, material:MaterialId) -> Object{
                 return Object{
                         shape:Shape::Sphere(
                                 Sphere{
@@ -38,3 +63,242 @@ impl Sphere{
                 return Sphere { position, radius, };
         }
 }
+
+//Infinite plane, the half space behind the normal counts as the inside of the object
+#[derive(Copy, Clone)]
+pub struct Plane{
+        pub position:Vector3,
+        pub normal:Vector3,
+}
+
+impl Plane{
+        pub fn create(position:Vector3, normal:Vector3, material:MaterialId) -> Object{
+                return Object{
+                        shape:Shape::Plane(Plane::create_plane(position, normal)),
+                        material,
+                };
+        }
+
+        pub fn create_plane(position:Vector3, normal:Vector3) -> Plane{
+                return Plane { position, normal:normal.normalize(), };
+        }
+}
+
+//Parallelogram spanned by the edges u and v from the corner position, normal is u x v
+#[derive(Copy, Clone)]
+pub struct Quad{
+        pub position:Vector3,
+        pub u:Vector3,
+        pub v:Vector3,
+}
+
+impl Quad{
+        pub fn create(position:Vector3, u:Vector3, v:Vector3, material:MaterialId) -> Object{
+                return Object{
+                        shape:Shape::Quad(Quad::create_quad(position, u, v)),
+                        material,
+                };
+        }
+
+        pub fn create_quad(position:Vector3, u:Vector3, v:Vector3) -> Quad{
+                return Quad { position, u, v, };
+        }
+
+        pub fn normal(&self) -> Vector3{
+                return self.u.cross(&self.v).normalize();
+        }
+}
+
+//Axis aligned box
+#[derive(Copy, Clone)]
+pub struct Cuboid{
+        pub min:Vector3,
+        pub max:Vector3,
+}
+
+impl Cuboid{
+        pub fn create(min:Vector3, max:Vector3, material:MaterialId) -> Object{
+                return Object{
+                        shape:Shape::Cuboid(Cuboid { min, max, }),
+                        material,
+                };
+        }
+}
+
+//Capped cylinder standing on base along the axis
+#[derive(Copy, Clone)]
+pub struct Cylinder{
+        pub base:Vector3,
+        pub axis:Vector3,
+        pub radius:f64,
+        pub height:f64,
+}
+
+impl Cylinder{
+        pub fn create(base:Vector3, axis:Vector3, radius:f64, height:f64, material:MaterialId) -> Object{
+                return Object{
+                        shape:Shape::Cylinder(Cylinder { base, axis:axis.normalize(), radius, height, }),
+                        material,
+                };
+        }
+}
+
+//Capped cone with the base disk at base and the apex at base + height * axis
+#[derive(Copy, Clone)]
+pub struct Cone{
+        pub base:Vector3,
+        pub axis:Vector3,
+        pub radius:f64,
+        pub height:f64,
+}
+
+impl Cone{
+        pub fn create(base:Vector3, axis:Vector3, radius:f64, height:f64, material:MaterialId) -> Object{
+                return Object{
+                        shape:Shape::Cone(Cone { base, axis:axis.normalize(), radius, height, }),
+                        material,
+                };
+        }
+
+        pub fn apex(&self) -> Vector3{
+                return self.base + self.height * self.axis;
+        }
+}
+
+//One sided disk, like quads the back face counts as the inside
+#[derive(Copy, Clone)]
+pub struct Disk{
+        pub position:Vector3,
+        pub normal:Vector3,
+        pub radius:f64,
+}
+
+impl Disk{
+        pub fn create(position:Vector3, normal:Vector3, radius:f64, material:MaterialId) -> Object{
+                return Object{
+                        shape:Shape::Disk(Disk { position, normal:normal.normalize(), radius, }),
+                        material,
+                };
+        }
+}
+
+//Segment from a to b swept by a sphere of radius
+#[derive(Copy, Clone)]
+pub struct Capsule{
+        pub a:Vector3,
+        pub b:Vector3,
+        pub radius:f64,
+}
+
+impl Capsule{
+        pub fn create(a:Vector3, b:Vector3, radius:f64, material:MaterialId) -> Object{
+                return Object{
+                        shape:Shape::Capsule(Capsule { a, b, radius, }),
+                        material,
+                };
+        }
+}
+
+//Shared shape placed with its own object to world transform, the geometry itself is never copied
+#[derive(Clone)]
+pub struct Instance{
+        pub shape:Arc<Shape>,
+        pub transform:Transform,
+}
+
+impl Instance{
+        pub fn create(shape:Arc<Shape>, transform:Transform, material:MaterialId) -> Object{
+                return Object{
+                        shape:Shape::Instance(Instance { shape, transform, }),
+                        material,
+                };
+        }
+}
+
+//Indexed triangle mesh, normals and uvs are per vertex and may be empty
+pub struct Mesh{
+        pub positions:Vec<Vector3>,
+        pub normals:Vec<Vector3>,
+        pub uvs:Vec<(f64, f64)>,
+        pub triangles:Vec<[u32; 3]>,
+        pub bvh:Bvh,
+        //Running sum of the triangle areas, for picking triangles by area
+        pub area_cdf:Vec<f64>,
+}
+
+impl Mesh{
+        pub fn create(mesh:Mesh, material:MaterialId) -> Object{
+                return Object{
+                        shape:Shape::Mesh(Arc::new(mesh)),
+                        material,
+                };
+        }
+
+        pub fn new(positions:Vec<Vector3>, normals:Vec<Vector3>, uvs:Vec<(f64, f64)>, triangles:Vec<[u32; 3]>) -> Mesh{
+                let mut mesh = Mesh{
+                        positions,
+                        normals,
+                        uvs,
+                        triangles,
+                        bvh:Bvh::empty(),
+                        area_cdf:Vec::new(),
+                };
+                mesh.build_bvh();
+                return mesh;
+        }
+
+        //Also refreshes the area table, call it after moving vertices
+        pub fn build_bvh(&mut self){
+                let bounds:Vec<Aabb> = (0..self.triangles.len()).map(|i| self.triangle_bounds(i)).collect();
+                self.bvh = Bvh::build(&bounds);
+
+                let mut area = 0.0;
+                self.area_cdf = (0..self.triangles.len()).map(|i| {
+                        area += self.triangle_area(i);
+                        area
+                }).collect();
+        }
+
+        pub fn triangle_area(&self, triangle:usize) -> f64{
+                let [p0, p1, p2] = self.triangles[triangle].map(|vertex| self.positions[vertex as usize]);
+                return 0.5 * (p1 - p0).cross(&(p2 - p0)).norm();
+        }
+
+        pub fn area(&self) -> f64{
+                return self.area_cdf.last().copied().unwrap_or(0.0);
+        }
+
+        pub fn triangle_bounds(&self, triangle:usize) -> Aabb{
+                let mut bounds = Aabb::empty();
+                for vertex in self.triangles[triangle]{
+                        bounds.grow(&self.positions[vertex as usize]);
+                }
+                return bounds;
+        }
+
+        pub fn has_normals(&self) -> bool{
+                return !self.normals.is_empty();
+        }
+
+        pub fn has_uvs(&self) -> bool{
+                return !self.uvs.is_empty();
+        }
+
+        //Area weighted smooth normals, for meshes that come without them
+        pub fn compute_normals(&mut self){
+                let mut normals = vec![Vector3::default(); self.positions.len()];
+
+                for triangle in &self.triangles{
+                        let p0 = self.positions[triangle[0] as usize];
+                        let p1 = self.positions[triangle[1] as usize];
+                        let p2 = self.positions[triangle[2] as usize];
+                        let face_normal = (p1 - p0).cross(&(p2 - p0));
+
+                        for vertex in triangle{
+                                normals[*vertex as usize] += face_normal;
+                        }
+                }
+
+                self.normals = normals.iter().map(|n| if n.norm_squared() > 0.0 {n.normalize()} else {*n}).collect();
+        }
+}
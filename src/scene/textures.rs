@@ -0,0 +1,292 @@
+use crate::vector3::Vector3;
+use crate::intersection::HitData;
+use super::noise;
+
+use std::sync::Arc;
+use image::{ImageBuffer, Rgb};
+
+//Color source evaluated per hit point, a material color is a Solid texture unless set otherwise
+#[derive(Clone)]
+pub enum Texture{
+	Solid(Vector3),
+	Checker(Checker),
+	Image(ImageTexture),
+	Noise(NoiseTexture),
+}
+
+//How a hit point is turned into texture coordinates
+#[derive(Copy, Clone)]
+pub enum Mapping{
+	//Surface uvs of the shape, scaled and then offset
+	Uv{scale:(f64, f64), offset:(f64, f64)},
+	//World space projections along x, y and z blended by the normal, for shapes with poor uvs
+	Triplanar{scale:f64, sharpness:f64},
+}
+
+#[derive(Clone)]
+pub struct Checker{
+	pub even:Box<Texture>,
+	pub odd:Box<Texture>,
+	pub mapping:Mapping,
+}
+
+//Texels are stored in linear space, shared between materials through the Arc
+#[derive(Clone)]
+pub struct ImageTexture{
+	pub image:Arc<ImageBuffer<Rgb<f32>, Vec<f32>>>,
+	pub mapping:Mapping,
+	pub bilinear:bool,
+}
+
+//Procedural pattern of the hit position, the pattern value in [0, 1] blends low and high
+#[derive(Clone)]
+pub struct NoiseTexture{
+	pub pattern:Pattern,
+	//Frequency, a feature of the pattern is about 1/scale world units wide
+	pub scale:f64,
+	pub low:Box<Texture>,
+	pub high:Box<Texture>,
+}
+
+#[derive(Copy, Clone)]
+pub enum Pattern{
+	Perlin,
+	Fbm{octaves:u32},
+	Turbulence{octaves:u32},
+	//Sine stripes along x distorted by turbulence
+	Marble{octaves:u32, distortion:f64},
+	//Concentric rings around the y axis, rings per unit of radius
+	Wood{rings:f64, distortion:f64},
+	//Distance to the closest feature point
+	Voronoi{jitter:f64},
+	//Distance between the closest two feature points, dark on cell borders
+	VoronoiEdges{jitter:f64},
+	//Flat random value per cell
+	VoronoiCells{jitter:f64},
+}
+
+impl Mapping{
+	pub const fn uv() -> Mapping{
+		Mapping::Uv{
+			scale:(1.0, 1.0),
+			offset:(0.0, 0.0),
+		}
+	}
+
+	pub const fn scaled_uv(u:f64, v:f64) -> Mapping{
+		Mapping::Uv{
+			scale:(u, v),
+			offset:(0.0, 0.0),
+		}
+	}
+
+	pub const fn triplanar(scale:f64) -> Mapping{
+		Mapping::Triplanar{
+			scale,
+			sharpness:4.0,
+		}
+	}
+}
+
+impl Texture{
+	pub const fn solid(color:Vector3) -> Texture{
+		Texture::Solid(color)
+	}
+
+	pub fn checker(even:Vector3, odd:Vector3, mapping:Mapping) -> Texture{
+		Texture::Checker(Checker{
+			even:Box::new(Texture::Solid(even)),
+			odd:Box::new(Texture::Solid(odd)),
+			mapping,
+		})
+	}
+
+	//Image in sRGB (png, jpeg...), decoded to linear colors
+	pub fn image(path:&str, mapping:Mapping) -> image::ImageResult<Texture>{
+		let mut image = image::open(path)?.into_rgb32f();
+		for pixel in image.pixels_mut(){
+			for c in pixel.0.iter_mut(){
+				*c = srgb_to_linear(*c);
+			}
+		}
+		return Ok(Texture::Image(ImageTexture{
+			image:Arc::new(image),
+			mapping,
+			bilinear:true,
+		}));
+	}
+
+	//Image whose values are data rather than colors and must not be linearized
+	pub fn image_linear(path:&str, mapping:Mapping) -> image::ImageResult<Texture>{
+		let image = image::open(path)?.into_rgb32f();
+		return Ok(Texture::Image(ImageTexture{
+			image:Arc::new(image),
+			mapping,
+			bilinear:true,
+		}));
+	}
+
+	pub fn noise(pattern:Pattern, scale:f64, low:Vector3, high:Vector3) -> Texture{
+		Texture::Noise(NoiseTexture{
+			pattern,
+			scale,
+			low:Box::new(Texture::Solid(low)),
+			high:Box::new(Texture::Solid(high)),
+		})
+	}
+
+	pub fn evaluate(&self, hit_data:&HitData) -> Vector3{
+		match self{
+			Texture::Solid(color) => *color,
+			Texture::Checker(c) => c.evaluate(hit_data),
+			Texture::Image(i) => i.evaluate(hit_data),
+			Texture::Noise(n) => n.evaluate(hit_data),
+		}
+	}
+
+	//Scalar parameters such as fuzz are stored as textures too, they read the mean of the channels
+	pub fn evaluate_scalar(&self, hit_data:&HitData) -> f64{
+		let value = self.evaluate(hit_data);
+		return (value.x + value.y + value.z) / 3.0;
+	}
+
+	//Value without a hit point, used where only a representative color is needed
+	pub fn average(&self) -> Vector3{
+		match self{
+			Texture::Solid(color) => *color,
+			Texture::Checker(c) => 0.5 * (c.even.average() + c.odd.average()),
+			Texture::Image(i) => i.average(),
+			Texture::Noise(n) => 0.5 * (n.low.average() + n.high.average()),
+		}
+	}
+}
+
+impl From<Vector3> for Texture{
+	fn from(color:Vector3) -> Texture{
+		return Texture::Solid(color);
+	}
+}
+
+impl From<f64> for Texture{
+	fn from(value:f64) -> Texture{
+		return Texture::Solid(Vector3::new(value, value, value));
+	}
+}
+
+impl Checker{
+	fn evaluate(&self, hit_data:&HitData) -> Vector3{
+		let parity = match self.mapping{
+			Mapping::Uv{scale, offset} => {
+				let u = (hit_data.uv.0 * scale.0 + offset.0).floor() as i64;
+				let v = (hit_data.uv.1 * scale.1 + offset.1).floor() as i64;
+				u + v
+			},
+			Mapping::Triplanar{scale, ..} => {
+				//A 3D checker is already seamless, no need to blend projections
+				let p = scale * hit_data.point;
+				p.x.floor() as i64 + p.y.floor() as i64 + p.z.floor() as i64
+			},
+		};
+
+		if parity.rem_euclid(2) == 0{
+			return self.even.evaluate(hit_data);
+		}
+		return self.odd.evaluate(hit_data);
+	}
+}
+
+impl ImageTexture{
+	fn evaluate(&self, hit_data:&HitData) -> Vector3{
+		match self.mapping{
+			Mapping::Uv{scale, offset} => {
+				return self.sample(hit_data.uv.0 * scale.0 + offset.0, hit_data.uv.1 * scale.1 + offset.1);
+			},
+			Mapping::Triplanar{scale, sharpness} => {
+				let p = scale * hit_data.point;
+				let n = hit_data.norm;
+				let mut weights = Vector3::new(n.x.abs().powf(sharpness), n.y.abs().powf(sharpness), n.z.abs().powf(sharpness));
+				weights = 1.0 / (weights.x + weights.y + weights.z) * weights;
+
+				return weights.x * self.sample(p.z, p.y) + weights.y * self.sample(p.x, p.z) + weights.z * self.sample(p.x, p.y);
+			},
+		}
+	}
+
+	//Repeating lookup, v = 0 is the bottom row of the image
+	pub fn sample(&self, u:f64, v:f64) -> Vector3{
+		let width = self.image.width() as i64;
+		let height = self.image.height() as i64;
+
+		let x = u.rem_euclid(1.0) * width as f64;
+		let y = (1.0 - v.rem_euclid(1.0)) * height as f64;
+
+		if !self.bilinear{
+			return self.texel(x as i64, y as i64);
+		}
+
+		//Texel centers are at half coordinates
+		let x = x - 0.5;
+		let y = y - 0.5;
+		let x0 = x.floor();
+		let y0 = y.floor();
+		let fx = x - x0;
+		let fy = y - y0;
+		let x0 = x0 as i64;
+		let y0 = y0 as i64;
+
+		let top = (1.0 - fx) * self.texel(x0, y0) + fx * self.texel(x0 + 1, y0);
+		let bottom = (1.0 - fx) * self.texel(x0, y0 + 1) + fx * self.texel(x0 + 1, y0 + 1);
+		return (1.0 - fy) * top + fy * bottom;
+	}
+
+	fn texel(&self, x:i64, y:i64) -> Vector3{
+		let x = x.rem_euclid(self.image.width() as i64) as u32;
+		let y = y.rem_euclid(self.image.height() as i64) as u32;
+		let pixel = self.image.get_pixel(x, y);
+		return Vector3::new(pixel[0] as f64, pixel[1] as f64, pixel[2] as f64);
+	}
+
+	fn average(&self) -> Vector3{
+		let mut sum = Vector3::new(0.0, 0.0, 0.0);
+		for pixel in self.image.pixels(){
+			sum += Vector3::new(pixel[0] as f64, pixel[1] as f64, pixel[2] as f64);
+		}
+		let count = (self.image.width() * self.image.height()).max(1) as f64;
+		return 1.0 / count * sum;
+	}
+}
+
+impl NoiseTexture{
+	fn evaluate(&self, hit_data:&HitData) -> Vector3{
+		let t = self.pattern.value(&(self.scale * hit_data.point)).clamp(0.0, 1.0);
+		return (1.0 - t) * self.low.evaluate(hit_data) + t * self.high.evaluate(hit_data);
+	}
+}
+
+impl Pattern{
+	pub fn value(&self, p:&Vector3) -> f64{
+		match *self{
+			Pattern::Perlin => 0.5 * (noise::perlin(p) + 1.0),
+			Pattern::Fbm{octaves} => 0.5 * (noise::fbm(p, octaves) + 1.0),
+			Pattern::Turbulence{octaves} => noise::turbulence(p, octaves),
+			Pattern::Marble{octaves, distortion} => 0.5 * (1.0 + (p.x + distortion * noise::turbulence(p, octaves)).sin()),
+			Pattern::Wood{rings, distortion} => {
+				let radius = (p.x * p.x + p.z * p.z).sqrt() * rings + distortion * noise::perlin(p);
+				radius - radius.floor()
+			},
+			Pattern::Voronoi{jitter} => noise::voronoi(p, jitter).f1,
+			Pattern::VoronoiEdges{jitter} => {
+				let v = noise::voronoi(p, jitter);
+				v.f2 - v.f1
+			},
+			Pattern::VoronoiCells{jitter} => noise::voronoi(p, jitter).cell,
+		}
+	}
+}
+
+fn srgb_to_linear(c:f32) -> f32{
+	if c <= 0.04045{
+		return c / 12.92;
+	}
+	return ((c + 0.055) / 1.055).powf(2.4);
+}
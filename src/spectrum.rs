@@ -0,0 +1,182 @@
+use crate::vector3::Vector3;
+
+use std::sync::OnceLock;
+
+//Spectral rendering support. A spectral camera sample carries three wavelengths and every radiance
+//Vector3 along its path holds one value per wavelength instead of RGB.
+
+pub const LAMBDA_MIN:f64 = 380.0;
+pub const LAMBDA_MAX:f64 = 720.0;
+const LAMBDA_RANGE:f64 = LAMBDA_MAX - LAMBDA_MIN;
+
+//Hero wavelength sampling (Wilkie et al. 2014), the other two are rotated by a third of the range
+#[derive(Copy, Clone, Debug)]
+pub struct Wavelengths{
+	pub lambda:Vector3,
+}
+
+impl Wavelengths{
+	//u uniform in [0, 1)
+	pub fn sample(u:f64) -> Wavelengths{
+		let hero = LAMBDA_MIN + u * LAMBDA_RANGE;
+		let rotate = |offset:f64| {
+			let lambda = hero + offset * LAMBDA_RANGE / 3.0;
+			if lambda >= LAMBDA_MAX {lambda - LAMBDA_RANGE} else {lambda}
+		};
+		Wavelengths{
+			lambda:Vector3::new(hero, rotate(1.0), rotate(2.0)),
+		}
+	}
+
+	pub fn hero(&self) -> f64{
+		return self.lambda.x;
+	}
+
+	//Keeps only the hero wavelength, for events that send each wavelength a different way. The
+	//returned weight must multiply the path so the estimate stays unbiased.
+	pub fn terminate_secondary(&mut self) -> Vector3{
+		if self.is_terminated() {return Vector3::new(1.0, 1.0, 1.0);}
+		self.lambda = Vector3::new(self.lambda.x, self.lambda.x, self.lambda.x);
+		return Vector3::new(3.0, 0.0, 0.0);
+	}
+
+	pub fn is_terminated(&self) -> bool{
+		return self.lambda.x == self.lambda.y && self.lambda.x == self.lambda.z;
+	}
+
+	//RGB color upsampled to the values of its spectrum at these wavelengths
+	pub fn from_rgb(&self, rgb:&Vector3) -> Vector3{
+		return Vector3::new(rgb_to_spectrum(rgb, self.lambda.x), rgb_to_spectrum(rgb, self.lambda.y), rgb_to_spectrum(rgb, self.lambda.z));
+	}
+
+	//Film conversion of the radiance carried for these wavelengths, a constant spectrum of 1 gives white.
+	//Saturated spectra give slightly negative channels, they are kept so averages stay unbiased.
+	pub fn to_rgb(&self, radiance:&Vector3) -> Vector3{
+		let mut xyz = Vector3::new(0.0, 0.0, 0.0);
+		for i in 0..3{
+			xyz += radiance.component(i) * cie_xyz(self.lambda.component(i));
+		}
+
+		let white = white_balance();
+		let rgb = xyz_to_rgb(&(LAMBDA_RANGE / 3.0 * xyz));
+		return Vector3::new(rgb.x / white.x, rgb.y / white.y, rgb.z / white.z);
+	}
+}
+
+//Colors are authored in RGB, in spectral mode they become values at the ray wavelengths
+pub fn carried(color:&Vector3, wavelengths:&Option<Wavelengths>) -> Vector3{
+	match wavelengths{
+		Some(w) => w.from_rgb(color),
+		None => *color,
+	}
+}
+
+//Smits 1999 basis spectra, 10 bins from LAMBDA_MIN to LAMBDA_MAX
+const WHITE:[f64; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const CYAN:[f64; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const MAGENTA:[f64; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const YELLOW:[f64; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const RED:[f64; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const GREEN:[f64; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const BLUE:[f64; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+//Linear interpolation between bin centers
+fn basis(table:&[f64; 10], lambda:f64) -> f64{
+	let x = ((lambda - LAMBDA_MIN) / LAMBDA_RANGE * 10.0 - 0.5).clamp(0.0, 9.0);
+	let i = (x.floor() as usize).min(8);
+	let t = x - i as f64;
+	return (1.0 - t) * table[i] + t * table[i + 1];
+}
+
+//Smits upsampling, white plus the two basis colors between the sorted channels
+pub fn rgb_to_spectrum(rgb:&Vector3, lambda:f64) -> f64{
+	let (r, g, b) = (rgb.x, rgb.y, rgb.z);
+
+	if r <= g && r <= b{
+		let mut value = r * basis(&WHITE, lambda);
+		if g <= b{
+			value += (g - r) * basis(&CYAN, lambda) + (b - g) * basis(&BLUE, lambda);
+		}else{
+			value += (b - r) * basis(&CYAN, lambda) + (g - b) * basis(&GREEN, lambda);
+		}
+		return value;
+	}
+
+	if g <= r && g <= b{
+		let mut value = g * basis(&WHITE, lambda);
+		if r <= b{
+			value += (r - g) * basis(&MAGENTA, lambda) + (b - r) * basis(&BLUE, lambda);
+		}else{
+			value += (b - g) * basis(&MAGENTA, lambda) + (r - b) * basis(&RED, lambda);
+		}
+		return value;
+	}
+
+	let mut value = b * basis(&WHITE, lambda);
+	if r <= g{
+		value += (r - b) * basis(&YELLOW, lambda) + (g - r) * basis(&GREEN, lambda);
+	}else{
+		value += (g - b) * basis(&YELLOW, lambda) + (r - g) * basis(&RED, lambda);
+	}
+	return value;
+}
+
+fn lobe(lambda:f64, mean:f64, sigma_low:f64, sigma_high:f64) -> f64{
+	let sigma = if lambda < mean {sigma_low} else {sigma_high};
+	let t = (lambda - mean) / sigma;
+	return (-0.5 * t * t).exp();
+}
+
+//CIE 1931 color matching functions, multi lobe fit of Wyman et al. 2013
+pub fn cie_xyz(lambda:f64) -> Vector3{
+	let x = 1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7) - 0.065 * lobe(lambda, 501.1, 20.4, 26.2);
+	let y = 0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1);
+	let z = 1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8);
+	return Vector3::new(x, y, z);
+}
+
+//Linear sRGB primaries
+pub fn xyz_to_rgb(xyz:&Vector3) -> Vector3{
+	return Vector3::new(
+		3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+		-0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+		0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z);
+}
+
+//RGB of a constant spectrum of 1 over the sampled range, dividing by it keeps white surfaces white
+fn white_balance() -> Vector3{
+	static WHITE_RGB:OnceLock<Vector3> = OnceLock::new();
+	return *WHITE_RGB.get_or_init(|| {
+		let steps = 1000;
+		let step = LAMBDA_RANGE / steps as f64;
+		let mut xyz = Vector3::new(0.0, 0.0, 0.0);
+		for i in 0..steps{
+			xyz += step * cie_xyz(LAMBDA_MIN + (i as f64 + 0.5) * step);
+		}
+		xyz_to_rgb(&xyz)
+	});
+}
+
+//Index of refraction as a function of wavelength
+#[derive(Copy, Clone, Debug)]
+pub enum Dispersion{
+	//n = a + b / lambda^2, lambda in micrometers
+	Cauchy{a:f64, b:f64},
+	//n^2 = 1 + sum b_i lambda^2 / (lambda^2 - c_i), lambda in micrometers
+	Sellmeier{b:[f64; 3], c:[f64; 3]},
+}
+
+impl Dispersion{
+	pub const DIAMOND:Dispersion = Dispersion::Cauchy{a:2.385, b:0.0117};
+	pub const BK7:Dispersion = Dispersion::Sellmeier{b:[1.03961212, 0.231792344, 1.01046945], c:[0.00600069867, 0.0200179144, 103.560653]};
+	pub const FUSED_SILICA:Dispersion = Dispersion::Sellmeier{b:[0.6961663, 0.4079426, 0.8974794], c:[0.0046791482, 0.0135120631, 97.9340025]};
+
+	pub fn ior(&self, lambda:f64) -> f64{
+		let micrometers = lambda / 1000.0;
+		let l2 = micrometers * micrometers;
+		match *self{
+			Dispersion::Cauchy{a, b} => a + b / l2,
+			Dispersion::Sellmeier{b, c} => (1.0 + b[0] * l2 / (l2 - c[0]) + b[1] * l2 / (l2 - c[1]) + b[2] * l2 / (l2 - c[2])).sqrt(),
+		}
+	}
+}
@@ -60,6 +60,14 @@ impl Vector3{
 	pub fn reflect(&self, norm:&Vector3) -> Vector3{
 		return self - 2.0 * self.dot(norm) * norm;
 	}
+
+	pub fn component(&self, axis:usize) -> f64{
+		match axis{
+			0 => self.x,
+			1 => self.y,
+			_ => self.z,
+		}
+	}
 }
 
 impl Default for Vector3{